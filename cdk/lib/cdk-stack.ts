@@ -64,7 +64,9 @@ export function stack(scope: Construct, id: string, props: StackProps) {
     bundling: {
       cargoLambdaFlags: ["--target", "aarch64-unknown-linux-musl"],
     },
-    events: [new SqsEventSource(apiQueue.sqsQueue)],
+    events: [
+      new SqsEventSource(apiQueue.sqsQueue, { reportBatchItemFailures: true }),
+    ],
     environment: {
       OTEL_ENDPOINT: "http://localhost:4317/v1/traces",
       DD_OTLP_CONFIG_RECEIVER_PROTOCOLS_GRPC_ENDPOINT: "localhost:4317",
@@ -80,7 +82,9 @@ export function stack(scope: Construct, id: string, props: StackProps) {
   });
 
   apiQueue.sqsQueue.grantConsumeMessages(handler);
-  bucket.grantPut(handler);
+  // Read and delete are needed for the quarantine failure history
+  bucket.grantReadWrite(handler);
+  bucket.grantDelete(handler);
 
   return stack;
 }
@@ -0,0 +1,327 @@
+use std::{collections::HashMap, path::PathBuf, time::Duration};
+
+use aws_sdk_s3::Client as S3Client;
+use aws_sdk_sqs::Client as SqsClient;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::{io::AsyncWriteExt, sync::Mutex};
+
+use crate::{
+    config::Conditions,
+    domain::{
+        conversation::{Conversation, SLAStatus},
+        notification::Notification,
+        timestamp::{self, TimestampFormat},
+        topic::Topic,
+        DateTime, Item,
+    },
+    error::{BoxError, Error, Result},
+};
+
+/// Emitted when a conversation matches an alert rule
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone)]
+pub struct Alert {
+    pub rule: String,
+    #[serde(with = "timestamp")]
+    #[schemars(schema_with = "timestamp::schema")]
+    pub triggered_at: DateTime,
+    pub notification_id: String,
+    pub topic: Topic,
+    pub conversation_id: String,
+    pub admin_assignee_id: Option<i32>,
+    pub team_assignee_id: Option<String>,
+    pub sla_name: Option<String>,
+    pub sla_status: Option<SLAStatus>,
+    pub rating: Option<i8>,
+}
+impl Alert {
+    pub fn new(
+        rule: &str,
+        notification: &Notification<Item>,
+        conversation: &Conversation,
+        now: DateTime,
+    ) -> Self {
+        Self {
+            rule: rule.into(),
+            triggered_at: now,
+            notification_id: notification.id.clone(),
+            topic: notification.topic.clone(),
+            conversation_id: conversation.id.clone(),
+            admin_assignee_id: conversation.admin_assignee_id,
+            team_assignee_id: conversation.team_assignee_id.clone(),
+            sla_name: conversation
+                .sla_applied
+                .as_ref()
+                .map(|x| x.sla_name.clone()),
+            sla_status: conversation
+                .sla_applied
+                .as_ref()
+                .map(|x| x.sla_status.clone()),
+            rating: conversation.conversation_rating.as_ref().map(|x| x.rating),
+        }
+    }
+
+    /// One line description for chat messages
+    pub fn summary(&self) -> String {
+        let mut summary = format!("{}: conversation {}", self.rule, self.conversation_id);
+        if let Some(status) = &self.sla_status {
+            let name = self.sla_name.as_deref().filter(|x| !x.is_empty());
+            summary += &format!(", SLA {} {status}", name.unwrap_or("status"));
+        }
+        if let Some(rating) = self.rating {
+            summary += &format!(", rated {rating}");
+        }
+        summary
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookFormat {
+    /// The alert as JSON
+    #[default]
+    Json,
+    /// A Slack compatible `{"text": ...}` message
+    Slack,
+}
+
+pub enum AlertSink {
+    Sqs {
+        client: SqsClient,
+        queue_url: String,
+    },
+    Webhook {
+        client: reqwest::Client,
+        url: String,
+        format: WebhookFormat,
+    },
+    /// Alerts are appended as JSON lines
+    File { path: PathBuf },
+}
+impl AlertSink {
+    fn name(&self) -> String {
+        match self {
+            Self::Sqs { queue_url, .. } => queue_url.clone(),
+            Self::Webhook { url, .. } => url.clone(),
+            Self::File { path } => path.display().to_string(),
+        }
+    }
+
+    /// Alerts are written with timestamps in `timestamps`, like the records they are about
+    pub async fn send(&self, alert: &Alert, timestamps: TimestampFormat) -> Result<()> {
+        let sink_write = |e: BoxError| Error::sink_write(self.name(), e);
+        let unserializable =
+            |e: serde_json::Error| Error::Validation(format!("unserializable alert: {e}"));
+
+        match self {
+            Self::Sqs { client, queue_url } => {
+                let body = timestamps
+                    .scope(|| serde_json::to_string(alert))
+                    .map_err(unserializable)?;
+                client
+                    .send_message()
+                    .queue_url(queue_url)
+                    .message_body(body)
+                    .send()
+                    .await
+                    .map_err(|e| sink_write(e.into()))?;
+            }
+            Self::Webhook {
+                client,
+                url,
+                format,
+            } => {
+                let body = match format {
+                    WebhookFormat::Json => timestamps
+                        .scope(|| serde_json::to_value(alert))
+                        .map_err(unserializable)?,
+                    WebhookFormat::Slack => serde_json::json!({ "text": alert.summary() }),
+                };
+                client
+                    .post(url)
+                    .json(&body)
+                    .send()
+                    .await
+                    .and_then(|x| x.error_for_status())
+                    .map_err(|e| sink_write(e.into()))?;
+            }
+            Self::File { path } => {
+                let mut line = timestamps
+                    .scope(|| serde_json::to_vec(alert))
+                    .map_err(unserializable)?;
+                line.push(b'\n');
+
+                let mut file = tokio::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .await
+                    .map_err(|e| sink_write(e.into()))?;
+                file.write_all(&line)
+                    .await
+                    .map_err(|e| sink_write(e.into()))?;
+                // Writes complete in the background unless flushed
+                file.flush().await.map_err(|e| sink_write(e.into()))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub struct AlertRule {
+    pub name: String,
+    pub when: Conditions,
+    pub sinks: Vec<AlertSink>,
+    /// A conversation alerts for a rule at most once in this window
+    pub dedup_window: Duration,
+}
+
+/// Whether an alert last sent at `last_alerted` is still within `window` at `now`
+pub fn within_window(last_alerted: Option<&DateTime>, now: &DateTime, window: Duration) -> bool {
+    last_alerted.is_some_and(|last| (*now - *last).to_std().is_ok_and(|x| x < window))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Marker {
+    alerted_at: DateTime,
+}
+
+/// S3 objects recording when each conversation last alerted, shared by every instance
+pub struct MarkerBucket {
+    pub client: S3Client,
+    pub bucket_name: String,
+    pub prefix: String,
+}
+impl MarkerBucket {
+    fn key(&self, key: &str) -> String {
+        format!("{}{key}.json", self.prefix)
+    }
+
+    async fn read(&self, key: &str) -> Result<Option<DateTime>> {
+        let response = self
+            .client
+            .get_object()
+            .bucket(&self.bucket_name)
+            .key(self.key(key))
+            .send()
+            .await;
+
+        let object = match response {
+            Ok(object) => object,
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_no_such_key()) => return Ok(None),
+            Err(e) => return Err(Error::sink_write(&self.bucket_name, e)),
+        };
+
+        let content = object
+            .body
+            .collect()
+            .await
+            .map_err(|e| Error::sink_write(&self.bucket_name, e))?
+            .into_bytes();
+        let marker: Marker = serde_json::from_slice(&content)
+            .map_err(|e| Error::Validation(format!("unreadable alert marker: {e}")))?;
+
+        Ok(Some(marker.alerted_at))
+    }
+
+    async fn write(&self, key: &str, alerted_at: DateTime) -> Result<()> {
+        let content = serde_json::to_vec(&Marker { alerted_at })
+            .map_err(|e| Error::Validation(format!("unserializable alert marker: {e}")))?;
+
+        self.client
+            .put_object()
+            .bucket(&self.bucket_name)
+            .key(self.key(key))
+            .body(content.into())
+            .send()
+            .await
+            .map_err(|e| Error::sink_write(&self.bucket_name, e))?;
+
+        Ok(())
+    }
+}
+
+/// Remembers when each conversation alerted for each rule, in memory for the warm instance
+/// and in `markers` when set so other instances see it too
+pub struct AlertDedup {
+    recent: Mutex<HashMap<String, DateTime>>,
+    markers: Option<MarkerBucket>,
+}
+impl AlertDedup {
+    pub fn new(markers: Option<MarkerBucket>) -> Self {
+        Self {
+            recent: Mutex::new(HashMap::new()),
+            markers,
+        }
+    }
+
+    fn key(rule: &str, conversation_id: &str) -> String {
+        format!("{rule}/{conversation_id}")
+    }
+
+    /// Whether the conversation already alerted for the rule within `window`
+    ///
+    /// Otherwise the alert is claimed at `now` before returning, so records for the same
+    /// conversation processed concurrently see it as a duplicate. A claimed alert that could
+    /// not be sent is given back with [`Self::release`]
+    pub async fn is_duplicate(
+        &self,
+        rule: &str,
+        conversation_id: &str,
+        now: &DateTime,
+        window: Duration,
+    ) -> Result<bool> {
+        let key = Self::key(rule, conversation_id);
+
+        {
+            let mut recent = self.recent.lock().await;
+            if within_window(recent.get(&key), now, window) {
+                return Ok(true);
+            }
+            recent.insert(key.clone(), *now);
+        }
+
+        let marker = match &self.markers {
+            Some(markers) => markers.read(&key).await,
+            None => Ok(None),
+        };
+        match marker {
+            Ok(Some(alerted_at)) if within_window(Some(&alerted_at), now, window) => {
+                self.recent.lock().await.insert(key, alerted_at);
+                Ok(true)
+            }
+            Ok(_) => Ok(false),
+            Err(e) => {
+                self.release(rule, conversation_id, now).await;
+                Err(e)
+            }
+        }
+    }
+
+    /// Give back the claim `is_duplicate` made at `claimed_at`, unless it has been replaced
+    pub async fn release(&self, rule: &str, conversation_id: &str, claimed_at: &DateTime) {
+        let key = Self::key(rule, conversation_id);
+
+        let mut recent = self.recent.lock().await;
+        if recent.get(&key) == Some(claimed_at) {
+            recent.remove(&key);
+        }
+    }
+
+    pub async fn record(
+        &self,
+        rule: &str,
+        conversation_id: &str,
+        alerted_at: DateTime,
+    ) -> Result<()> {
+        let key = Self::key(rule, conversation_id);
+
+        if let Some(markers) = &self.markers {
+            markers.write(&key, alerted_at).await?;
+        }
+        self.recent.lock().await.insert(key, alerted_at);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,931 @@
+use std::{collections::HashMap, path::PathBuf, str::FromStr, sync::Arc, time::Duration};
+
+use aws_sdk_s3::Client as S3Client;
+use aws_sdk_sqs::Client as SqsClient;
+use serde::Deserialize;
+
+use crate::{
+    alerts::{AlertDedup, AlertRule, AlertSink, MarkerBucket, WebhookFormat},
+    directory::{CachedDirectory, DirectorySource},
+    domain::{
+        contact::Contact,
+        conversation::{ConversationState, SLAStatus, SourceType},
+        notification::Notification,
+        timestamp::TimestampFormat,
+        topic::Topic,
+        DateTime, Item,
+    },
+    error::{env_var, Error, Result},
+    intercom::{self, IntercomClient},
+    utils::glob_match,
+    workflow::{
+        AcceptTopics, AcceptWorkspaces, Alerts, ApplyFilters, ArchiveRaw, Decode, Enrich,
+        ErrorPolicy, HeartbeatObject, Heartbeats, Parse, PiiHandling, Pipeline, ProtectPii, Redact,
+        ResolveDirectory, RouteAvailability, RouteByTopic, S3Sink, SkipUnmodelled, Validate,
+        Verify,
+    },
+};
+
+/// Used when `PIPELINE_CONFIG` is not set
+const BUNDLED_CONFIG: &str = include_str!("../pipeline.toml");
+
+/// How the output document is encoded
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputFormat {
+    #[default]
+    Json,
+    PrettyJson,
+    /// JSON followed by a newline, so objects can be concatenated
+    Ndjson,
+}
+impl OutputFormat {
+    pub fn encode(&self, document: &serde_json::Value) -> serde_json::Result<Vec<u8>> {
+        match self {
+            Self::Json => serde_json::to_vec(document),
+            Self::PrettyJson => serde_json::to_vec_pretty(document),
+            Self::Ndjson => {
+                let mut content = serde_json::to_vec(document)?;
+                content.push(b'\n');
+                Ok(content)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Placeholder {
+    /// `%Y%m%d` of the time the record was processed
+    Date,
+    /// Topic with dots replaced by underscores
+    Topic,
+    /// Topic with dots replaced by slashes
+    TopicPath,
+    /// What the topic is about, such as `conversation`, or `unknown`
+    Object,
+    NotificationId,
+    /// Id of the notification item
+    ItemId,
+    /// The workspace the notification is from
+    AppId,
+    /// Derived from the SQS message id, so a record delivered again writes the same key
+    Uuid,
+}
+impl FromStr for Placeholder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "date" => Ok(Self::Date),
+            "topic" => Ok(Self::Topic),
+            "topic_path" => Ok(Self::TopicPath),
+            "object" => Ok(Self::Object),
+            "notification_id" => Ok(Self::NotificationId),
+            "item_id" => Ok(Self::ItemId),
+            "app_id" => Ok(Self::AppId),
+            "uuid" => Ok(Self::Uuid),
+            other => Err(format!("unknown placeholder {{{other}}}")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Placeholder(Placeholder),
+}
+
+/// Values available to a key template
+pub struct KeyFields<'a> {
+    pub now: &'a DateTime,
+    pub topic: &'a Topic,
+    pub notification_id: &'a str,
+    pub item_id: &'a str,
+    pub app_id: &'a str,
+    pub uuid: &'a uuid::Uuid,
+}
+
+/// An object key with `{placeholder}` substitutions, such as `{date}_{topic}_{uuid}.json`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyTemplate(Vec<Part>);
+impl KeyTemplate {
+    pub const DEFAULT: &'static str = "{date}_{topic}_{uuid}.json";
+
+    pub fn render(&self, fields: &KeyFields) -> String {
+        self.0
+            .iter()
+            .map(|part| match part {
+                Part::Literal(literal) => literal.clone(),
+                Part::Placeholder(Placeholder::Date) => fields.now.format("%Y%m%d").to_string(),
+                Part::Placeholder(Placeholder::Topic) => fields.topic.sanitized().replace('.', "_"),
+                Part::Placeholder(Placeholder::TopicPath) => {
+                    fields.topic.sanitized().replace('.', "/")
+                }
+                Part::Placeholder(Placeholder::Object) => fields.topic.label().into(),
+                Part::Placeholder(Placeholder::NotificationId) => fields.notification_id.into(),
+                Part::Placeholder(Placeholder::ItemId) => fields.item_id.into(),
+                Part::Placeholder(Placeholder::AppId) => fields.app_id.into(),
+                Part::Placeholder(Placeholder::Uuid) => fields.uuid.to_string(),
+            })
+            .collect()
+    }
+}
+impl Default for KeyTemplate {
+    fn default() -> Self {
+        Self::DEFAULT
+            .parse()
+            .expect("default key template is valid")
+    }
+}
+impl FromStr for KeyTemplate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = vec![];
+        let mut rest = s;
+
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                parts.push(Part::Literal(rest[..start].into()));
+            }
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| format!("unclosed placeholder in {s:?}"))?;
+            parts.push(Part::Placeholder(rest[start + 1..start + end].parse()?));
+            rest = &rest[start + end + 1..];
+        }
+        if rest.contains('}') {
+            return Err(format!("unmatched }} in {s:?}"));
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Literal(rest.into()));
+        }
+
+        if !parts.contains(&Part::Placeholder(Placeholder::Uuid)) {
+            return Err(format!(
+                "{s:?} must contain {{uuid}} so that records do not overwrite each other"
+            ));
+        }
+
+        Ok(Self(parts))
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum SinkConfig {
+    /// Bucket defaults to `OUTPUT_BUCKET`
+    S3 { bucket: Option<String> },
+}
+
+/// Settings shared by every route unless the route overrides them
+#[derive(Debug, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct Defaults {
+    format: Option<OutputFormat>,
+    key_template: Option<String>,
+    redact: Option<Vec<String>>,
+    sinks: Option<Vec<SinkConfig>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RouteConfig {
+    name: Option<String>,
+    topics: Vec<String>,
+    app_ids: Option<Vec<String>>,
+    format: Option<OutputFormat>,
+    key_template: Option<String>,
+    redact: Option<Vec<String>>,
+    sinks: Option<Vec<SinkConfig>>,
+}
+
+/// The file format, see `pipeline.toml` for an example
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    #[serde(default)]
+    defaults: Defaults,
+    #[serde(default)]
+    filters: Vec<Filter>,
+    enrichment: Option<Enrichment>,
+    directory: Option<DirectoryConfig>,
+    #[serde(default)]
+    alerts: Vec<AlertConfig>,
+    pii: Option<PiiConfig>,
+    availability: Option<AvailabilityConfig>,
+    heartbeat: Option<HeartbeatConfig>,
+    workspaces: Option<Workspaces>,
+    raw: Option<RawConfig>,
+    #[serde(default)]
+    timestamps: TimestampFormat,
+    routes: Vec<RouteConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawConfig {
+    bucket: Option<String>,
+    key_template: Option<String>,
+}
+
+/// Where every body is written as it was received, before it is parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawArchive {
+    /// Defaults to `OUTPUT_BUCKET`
+    pub bucket: Option<String>,
+    pub key_template: KeyTemplate,
+}
+impl RawArchive {
+    pub const DEFAULT_KEY_TEMPLATE: &'static str = "raw/{date}/{topic}_{uuid}.json";
+}
+
+/// The Intercom workspaces, by app id, whose notifications are accepted
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Workspaces {
+    pub allow: Vec<String>,
+}
+
+/// Where the heartbeat of `ping` notifications is written, `{prefix}{app_id}.json`
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct HeartbeatConfig {
+    /// Defaults to `OUTPUT_BUCKET`
+    pub bucket: Option<String>,
+    #[serde(default = "HeartbeatConfig::default_prefix")]
+    pub prefix: String,
+}
+impl HeartbeatConfig {
+    fn default_prefix() -> String {
+        "heartbeats/".into()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AvailabilityConfig {
+    bucket: Option<String>,
+    key_template: Option<String>,
+    format: Option<OutputFormat>,
+}
+
+/// Where the teammate availability facts of `admin.away_mode_updated` are written
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AvailabilityStream {
+    /// Defaults to `OUTPUT_BUCKET`
+    pub bucket: Option<String>,
+    pub key_template: KeyTemplate,
+    pub format: OutputFormat,
+}
+impl AvailabilityStream {
+    pub const DEFAULT_KEY_TEMPLATE: &'static str = "availability/{date}/{item_id}_{uuid}.json";
+}
+
+/// Personal details of contacts in `contact.*` notifications
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct PiiConfig {
+    pub handling: PiiHandling,
+    /// Top-level fields of the contact, defaults to `Contact::PII_FIELDS`
+    #[serde(default = "PiiConfig::default_fields")]
+    pub fields: Vec<String>,
+}
+impl PiiConfig {
+    fn default_fields() -> Vec<String> {
+        Contact::PII_FIELDS.iter().map(|x| x.to_string()).collect()
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum AlertSinkConfig {
+    Sqs {
+        queue_url: String,
+    },
+    Webhook {
+        url: String,
+        #[serde(default)]
+        format: WebhookFormat,
+    },
+    /// Alerts are appended to the file as JSON lines
+    File {
+        path: PathBuf,
+    },
+}
+
+/// A rule that emits an alert when a conversation matches `when`
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct AlertConfig {
+    pub name: String,
+    pub when: Conditions,
+    pub sinks: Vec<AlertSinkConfig>,
+    /// A conversation alerts for the rule at most once in this window
+    #[serde(default = "AlertConfig::default_dedup_secs")]
+    pub dedup_secs: u64,
+}
+impl AlertConfig {
+    fn default_dedup_secs() -> u64 {
+        24 * 60 * 60
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum DirectorySourceConfig {
+    /// The `/admins` and `/teams` APIs, with the token in `INTERCOM_ACCESS_TOKEN`
+    Api {
+        #[serde(default = "Enrichment::default_api_url")]
+        api_url: String,
+    },
+    /// A JSON file with `admins` and `teams` lists in the same shape as the APIs
+    File { path: PathBuf },
+}
+
+/// Resolving admin and team ids to names and emails
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct DirectoryConfig {
+    pub source: DirectorySourceConfig,
+    /// How long a loaded directory is used before it is reloaded
+    #[serde(default = "DirectoryConfig::default_ttl_secs")]
+    pub ttl_secs: u64,
+    /// Names and emails are extras, so by default the record is stored without them
+    /// when the directory cannot be loaded
+    #[serde(default = "DirectoryConfig::default_on_error")]
+    pub on_error: ErrorPolicy,
+}
+impl DirectoryConfig {
+    fn default_ttl_secs() -> u64 {
+        3600
+    }
+
+    fn default_on_error() -> ErrorPolicy {
+        ErrorPolicy::Skip
+    }
+}
+
+/// Fetching conversations, and optionally their contacts, from the Intercom REST API
+/// with the token in `INTERCOM_ACCESS_TOKEN`
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Enrichment {
+    #[serde(default = "Enrichment::default_topics")]
+    pub topics: Vec<String>,
+    #[serde(default)]
+    pub contacts: bool,
+    /// `skip` stores the record without enrichment when the API cannot be reached
+    #[serde(default)]
+    pub on_error: ErrorPolicy,
+    #[serde(default = "Enrichment::default_api_url")]
+    pub api_url: String,
+    #[serde(default = "Enrichment::default_max_retries")]
+    pub max_retries: u32,
+    /// Delay before the first retry of a server error, doubled on every later retry
+    #[serde(default = "Enrichment::default_backoff_ms")]
+    pub backoff_ms: u64,
+}
+impl Enrichment {
+    fn default_topics() -> Vec<String> {
+        vec!["conversation.*".into()]
+    }
+
+    fn default_api_url() -> String {
+        intercom::DEFAULT_API_URL.into()
+    }
+
+    fn default_max_retries() -> u32 {
+        3
+    }
+
+    fn default_backoff_ms() -> u64 {
+        200
+    }
+}
+
+/// Predicates over a notification, every condition that is set must match
+/// and a list matches when any of its values does
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Conditions {
+    pub topics: Option<Vec<String>>,
+    pub app_id: Option<Vec<String>>,
+    pub team_assignee_id: Option<Vec<String>>,
+    /// Tag names, matches when the conversation has any of them
+    pub tags: Option<Vec<String>>,
+    pub state: Option<Vec<ConversationState>>,
+    pub ai_agent_source_type: Option<Vec<SourceType>>,
+    /// Attribute values may use `*`, matches when every attribute matches
+    pub custom_attributes: Option<HashMap<String, String>>,
+    pub sla_status: Option<Vec<SLAStatus>>,
+    /// Matches conversations rated this or lower, unrated conversations do not match
+    pub max_rating: Option<i8>,
+}
+impl Conditions {
+    fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// Conditions other than `topics` and `app_id` are about conversations
+    /// and do not match other items
+    pub fn matches(&self, notification: &Notification<Item>) -> bool {
+        let topics = self
+            .topics
+            .as_ref()
+            .is_none_or(|topics| topics.iter().any(|x| notification.topic.matches(x)));
+        let app_id = self
+            .app_id
+            .as_ref()
+            .is_none_or(|ids| ids.contains(&notification.app_id));
+        let Some(conversation) = notification.data.as_conversation() else {
+            let any_item = Self {
+                topics: self.topics.clone(),
+                app_id: self.app_id.clone(),
+                ..Default::default()
+            };
+            return topics && app_id && self == &any_item;
+        };
+
+        let team_assignee_id = self.team_assignee_id.as_ref().is_none_or(|ids| {
+            conversation
+                .team_assignee_id
+                .as_ref()
+                .is_some_and(|id| ids.contains(id))
+        });
+        let tags = self.tags.as_ref().is_none_or(|names| {
+            conversation
+                .tags
+                .iter()
+                .any(|tag| names.contains(&tag.name))
+        });
+        let state = self
+            .state
+            .as_ref()
+            .is_none_or(|states| states.contains(&conversation.state));
+        let ai_agent_source_type = self
+            .ai_agent_source_type
+            .as_ref()
+            .is_none_or(|types| types.contains(&conversation.ai_agent.source_type));
+        let custom_attributes = self.custom_attributes.as_ref().is_none_or(|attributes| {
+            attributes.iter().all(|(name, pattern)| {
+                conversation
+                    .custom_attributes
+                    .get(name)
+                    .is_some_and(|value| glob_match(pattern, value))
+            })
+        });
+
+        let sla_status = self.sla_status.as_ref().is_none_or(|statuses| {
+            conversation
+                .sla_applied
+                .as_ref()
+                .is_some_and(|x| statuses.contains(&x.sla_status))
+        });
+        let max_rating = self.max_rating.is_none_or(|max| {
+            conversation
+                .conversation_rating
+                .as_ref()
+                .is_some_and(|x| x.rating <= max)
+        });
+
+        topics
+            && app_id
+            && team_assignee_id
+            && tags
+            && state
+            && ai_agent_source_type
+            && custom_attributes
+            && sla_status
+            && max_rating
+    }
+}
+
+/// What happens to a record matching a filter
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum FilterAction {
+    /// Acknowledge the record without storing it
+    Drop,
+    /// Use the named route instead of the one matching the topic
+    Route { route: String },
+    /// Add `label` to the `labels` of the output document
+    Tag { label: String },
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Filter {
+    /// Reported with the dropped records metric
+    pub name: String,
+    pub when: Conditions,
+    pub action: FilterAction,
+}
+
+/// Processing for the topics that match one of `topics`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Route {
+    /// Lets filters send records to this route
+    pub name: Option<String>,
+    pub topics: Vec<String>,
+    /// Workspaces the route is for, every workspace when not set
+    pub app_ids: Option<Vec<String>>,
+    pub format: OutputFormat,
+    pub key_template: KeyTemplate,
+    /// Dotted paths into the output document, `*` matches every key or array element
+    pub redact: Vec<String>,
+    pub sinks: Vec<SinkConfig>,
+}
+impl Route {
+    pub fn matches(&self, topic: &Topic, app_id: &str) -> bool {
+        self.topics.iter().any(|pattern| topic.matches(pattern))
+            && self
+                .app_ids
+                .as_ref()
+                .is_none_or(|app_ids| app_ids.iter().any(|x| x == app_id))
+    }
+}
+
+/// Filters are applied in order before routing, then routes are tried in order,
+/// the first to match a topic is used and notifications matching no route are not accepted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PipelineConfig {
+    pub filters: Vec<Filter>,
+    pub enrichment: Option<Enrichment>,
+    pub directory: Option<DirectoryConfig>,
+    pub alerts: Vec<AlertConfig>,
+    pub pii: Option<PiiConfig>,
+    pub availability: Option<AvailabilityStream>,
+    pub heartbeat: Option<HeartbeatConfig>,
+    /// Every workspace is accepted when not set
+    pub workspaces: Option<Workspaces>,
+    pub raw: Option<RawArchive>,
+    /// How timestamps are written in every output
+    pub timestamps: TimestampFormat,
+    pub routes: Vec<Route>,
+}
+
+/// What the pipeline takes from the environment rather than the configuration file
+pub struct Environment<'a> {
+    pub s3_client: &'a S3Client,
+    pub sqs_client: &'a SqsClient,
+    /// Used by sinks without a bucket and for alert dedup markers
+    pub default_bucket: Option<String>,
+    /// Signatures are verified when set
+    pub client_secret: Option<String>,
+    /// Required when the Intercom API is used
+    pub access_token: Option<String>,
+}
+impl PipelineConfig {
+    /// Load from the file at `PIPELINE_CONFIG`, or the bundled `pipeline.toml`
+    pub fn from_env() -> Result<Self> {
+        match env_var("PIPELINE_CONFIG") {
+            Ok(path) => {
+                let content =
+                    std::fs::read_to_string(&path).map_err(|e| Error::configuration(&path, e))?;
+                Self::parse(&content).map_err(|reason| Error::configuration(&path, reason))
+            }
+            Err(_) => Self::parse(BUNDLED_CONFIG)
+                .map_err(|reason| Error::configuration("pipeline.toml", reason)),
+        }
+    }
+
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let file: ConfigFile = toml::from_str(content).map_err(|e| e.to_string())?;
+        let defaults = file.defaults;
+
+        if file.routes.is_empty() {
+            return Err("at least one route is required".into());
+        }
+
+        let routes = file
+            .routes
+            .into_iter()
+            .enumerate()
+            .map(|(i, route)| {
+                let context = |e: String| format!("routes[{i}]: {e}");
+
+                if route.topics.is_empty() || route.topics.iter().any(|x| x.is_empty()) {
+                    return Err(context(
+                        "topics must be a list of non-empty patterns".into(),
+                    ));
+                }
+
+                let redact = route
+                    .redact
+                    .or_else(|| defaults.redact.clone())
+                    .unwrap_or_default();
+                if let Some(path) = redact
+                    .iter()
+                    .find(|path| path.split('.').any(str::is_empty))
+                {
+                    return Err(context(format!("invalid redact path {path:?}")));
+                }
+
+                let sinks = route
+                    .sinks
+                    .or_else(|| defaults.sinks.clone())
+                    .unwrap_or_else(|| vec![SinkConfig::S3 { bucket: None }]);
+                if sinks.is_empty() {
+                    return Err(context("at least one sink is required".into()));
+                }
+
+                let key_template = route
+                    .key_template
+                    .as_ref()
+                    .or(defaults.key_template.as_ref())
+                    .map(|x| x.parse())
+                    .transpose()
+                    .map_err(context)?
+                    .unwrap_or_default();
+
+                if route.app_ids.as_ref().is_some_and(|x| x.is_empty()) {
+                    return Err(context("app_ids must not be empty when set".into()));
+                }
+
+                Ok(Route {
+                    name: route.name,
+                    topics: route.topics,
+                    app_ids: route.app_ids,
+                    format: route.format.or(defaults.format).unwrap_or_default(),
+                    key_template,
+                    redact,
+                    sinks,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut names = std::collections::HashSet::new();
+        if let Some(name) = routes
+            .iter()
+            .filter_map(|route| route.name.as_ref())
+            .find(|name| !names.insert(*name))
+        {
+            return Err(format!("route name {name:?} is used more than once"));
+        }
+
+        for (i, filter) in file.filters.iter().enumerate() {
+            let context = |e: String| format!("filters[{i}]: {e}");
+
+            if filter.name.is_empty() {
+                return Err(context("name must not be empty".into()));
+            }
+            if filter.when.is_empty() {
+                return Err(context("at least one condition is required".into()));
+            }
+            if let FilterAction::Route { route } = &filter.action {
+                if !names.contains(route) {
+                    return Err(context(format!("unknown route {route:?}")));
+                }
+            }
+        }
+
+        let mut alert_names = std::collections::HashSet::new();
+        for (i, alert) in file.alerts.iter().enumerate() {
+            let context = |e: String| format!("alerts[{i}]: {e}");
+
+            if alert.name.is_empty() {
+                return Err(context("name must not be empty".into()));
+            }
+            if !alert_names.insert(&alert.name) {
+                return Err(context(format!(
+                    "name {:?} is used more than once",
+                    alert.name
+                )));
+            }
+            if alert.when.is_empty() {
+                return Err(context("at least one condition is required".into()));
+            }
+            if alert.sinks.is_empty() {
+                return Err(context("at least one sink is required".into()));
+            }
+        }
+
+        if file
+            .workspaces
+            .as_ref()
+            .is_some_and(|x| x.allow.is_empty() || x.allow.iter().any(String::is_empty))
+        {
+            return Err("workspaces: allow must be a list of app ids".into());
+        }
+
+        if let Some(pii) = &file.pii {
+            if pii.fields.iter().any(|x| x.is_empty()) {
+                return Err("pii: fields must not be empty".into());
+            }
+        }
+
+        let availability = file
+            .availability
+            .map(|availability| {
+                let key_template = availability
+                    .key_template
+                    .as_deref()
+                    .unwrap_or(AvailabilityStream::DEFAULT_KEY_TEMPLATE)
+                    .parse()
+                    .map_err(|e| format!("availability: {e}"))?;
+
+                Ok::<_, String>(AvailabilityStream {
+                    bucket: availability.bucket,
+                    key_template,
+                    format: availability.format.unwrap_or_default(),
+                })
+            })
+            .transpose()?;
+
+        let raw = file
+            .raw
+            .map(|raw| {
+                let key_template = raw
+                    .key_template
+                    .as_deref()
+                    .unwrap_or(RawArchive::DEFAULT_KEY_TEMPLATE)
+                    .parse()
+                    .map_err(|e| format!("raw: {e}"))?;
+
+                Ok::<_, String>(RawArchive {
+                    bucket: raw.bucket,
+                    key_template,
+                })
+            })
+            .transpose()?;
+
+        Ok(Self {
+            filters: file.filters,
+            enrichment: file.enrichment,
+            directory: file.directory,
+            alerts: file.alerts,
+            pii: file.pii,
+            availability,
+            heartbeat: file.heartbeat,
+            workspaces: file.workspaces,
+            raw,
+            timestamps: file.timestamps,
+            routes,
+        })
+    }
+
+    /// Send sinks, the raw archive, the availability stream and heartbeats
+    /// without a bucket to `default_bucket`
+    pub fn with_default_bucket(mut self, default_bucket: Option<&str>) -> Result<Self> {
+        let buckets = self
+            .routes
+            .iter_mut()
+            .flat_map(|route| &mut route.sinks)
+            .map(|SinkConfig::S3 { bucket }| bucket)
+            .chain(self.availability.as_mut().map(|x| &mut x.bucket))
+            .chain(self.heartbeat.as_mut().map(|x| &mut x.bucket))
+            .chain(self.raw.as_mut().map(|x| &mut x.bucket));
+
+        for bucket in buckets {
+            if bucket.is_none() {
+                let default_bucket = default_bucket.ok_or_else(|| {
+                    Error::configuration("OUTPUT_BUCKET", "required by sinks without a bucket")
+                })?;
+                *bucket = Some(default_bucket.into());
+            }
+        }
+
+        Ok(self)
+    }
+
+    /// Build the handler pipeline
+    pub fn build(self, env: Environment) -> Result<Pipeline> {
+        let config = self.with_default_bucket(env.default_bucket.as_deref())?;
+        let routes = Arc::new(config.routes);
+
+        let heartbeat = config.heartbeat.and_then(|heartbeat| {
+            Some(HeartbeatObject {
+                client: env.s3_client.clone(),
+                bucket: heartbeat.bucket?,
+                prefix: heartbeat.prefix,
+            })
+        });
+
+        let pipeline = Pipeline::new()
+            .with_timestamps(config.timestamps)
+            .with_stage(Decode)
+            .with_stage(Heartbeats::new(heartbeat))
+            .with_stage(AcceptTopics::new(routes.clone()).with_filters(&config.filters))
+            .with_stage(Parse)
+            .with_stage(Validate)
+            .with_stage(AcceptWorkspaces::new(config.workspaces.map(|x| x.allow)))
+            .with_stage(ApplyFilters::new(config.filters))
+            .with_stage(Redact::new(routes.clone()))
+            .with_stage(RouteByTopic::new(routes))
+            .with_stage(S3Sink::new(env.s3_client.clone()));
+
+        let pipeline = match config.raw {
+            Some(RawArchive {
+                bucket: Some(bucket),
+                key_template,
+            }) => pipeline
+                .with_stage(ArchiveRaw::new(env.s3_client.clone(), bucket, key_template))
+                .with_stage(SkipUnmodelled),
+            _ => pipeline,
+        };
+
+        let pipeline = match config.availability {
+            Some(AvailabilityStream {
+                bucket: Some(bucket),
+                key_template,
+                format,
+            }) => pipeline.with_stage(RouteAvailability::new(bucket, key_template, format)),
+            _ => pipeline,
+        };
+
+        let pipeline = match config.pii.clone() {
+            Some(pii) => pipeline.with_stage(ProtectPii::new(pii.handling, pii.fields)),
+            None => pipeline,
+        };
+
+        let pipeline = match env.client_secret {
+            Some(secret) => pipeline.with_stage(Verify::new(secret)),
+            None => pipeline,
+        };
+
+        let intercom_client = |api_url: String| {
+            let access_token = env.access_token.clone().ok_or_else(|| {
+                Error::configuration("INTERCOM_ACCESS_TOKEN", "required by the Intercom API")
+            })?;
+            IntercomClient::new(api_url, access_token)
+        };
+
+        let pipeline = match config.enrichment {
+            Some(enrichment) => {
+                let client = intercom_client(enrichment.api_url)?
+                    .with_max_retries(enrichment.max_retries)
+                    .with_backoff(Duration::from_millis(enrichment.backoff_ms));
+
+                let enrich = Enrich::new(
+                    client,
+                    enrichment.topics,
+                    enrichment.contacts,
+                    enrichment.on_error,
+                );
+                pipeline.with_stage(match config.pii {
+                    Some(pii) => enrich.with_pii(pii.handling, pii.fields),
+                    None => enrich,
+                })
+            }
+            None => pipeline,
+        };
+
+        let pipeline = match config.directory {
+            Some(directory) => {
+                let source = match directory.source {
+                    DirectorySourceConfig::Api { api_url } => {
+                        DirectorySource::Api(intercom_client(api_url)?)
+                    }
+                    DirectorySourceConfig::File { path } => DirectorySource::File(path),
+                };
+                let directory_cache =
+                    CachedDirectory::new(source, Duration::from_secs(directory.ttl_secs));
+
+                pipeline.with_stage(ResolveDirectory::new(directory_cache, directory.on_error))
+            }
+            None => pipeline,
+        };
+
+        if config.alerts.is_empty() {
+            return Ok(pipeline);
+        }
+
+        let bucket_name = env.default_bucket.ok_or_else(|| {
+            Error::configuration("OUTPUT_BUCKET", "required for alert dedup markers")
+        })?;
+        let dedup = AlertDedup::new(Some(MarkerBucket {
+            client: env.s3_client.clone(),
+            bucket_name,
+            prefix: "alerts/".into(),
+        }));
+        let http = reqwest::Client::new();
+        let rules = config
+            .alerts
+            .into_iter()
+            .map(|alert| AlertRule {
+                name: alert.name,
+                when: alert.when,
+                sinks: alert
+                    .sinks
+                    .into_iter()
+                    .map(|sink| match sink {
+                        AlertSinkConfig::Sqs { queue_url } => AlertSink::Sqs {
+                            client: env.sqs_client.clone(),
+                            queue_url,
+                        },
+                        AlertSinkConfig::Webhook { url, format } => AlertSink::Webhook {
+                            client: http.clone(),
+                            url,
+                            format,
+                        },
+                        AlertSinkConfig::File { path } => AlertSink::File { path },
+                    })
+                    .collect(),
+                dedup_window: Duration::from_secs(alert.dedup_secs),
+            })
+            .collect();
+
+        Ok(pipeline.with_stage(Alerts::new(rules, dedup)))
+    }
+}
@@ -0,0 +1,136 @@
+use std::{collections::HashMap, path::PathBuf, sync::Arc, time::Duration};
+
+use lambda_runtime::tracing;
+use serde::{Deserialize, Serialize};
+use tokio::{sync::Mutex, time::Instant};
+
+use crate::{
+    error::{Error, Result},
+    intercom::IntercomClient,
+};
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct Admin {
+    pub id: String,
+    pub name: String,
+    pub email: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct Team {
+    pub id: String,
+    pub name: String,
+}
+
+/// Admins and teams by id
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Directory {
+    admins: HashMap<String, Admin>,
+    teams: HashMap<String, Team>,
+}
+impl Directory {
+    pub fn new(admins: Vec<Admin>, teams: Vec<Team>) -> Self {
+        Self {
+            admins: admins.into_iter().map(|x| (x.id.clone(), x)).collect(),
+            teams: teams.into_iter().map(|x| (x.id.clone(), x)).collect(),
+        }
+    }
+
+    pub fn admin(&self, id: &str) -> Option<&Admin> {
+        self.admins.get(id)
+    }
+
+    pub fn team(&self, id: &str) -> Option<&Team> {
+        self.teams.get(id)
+    }
+}
+
+/// The shape of the `/admins` and `/teams` responses, and of a static directory file
+/// which holds both lists
+#[derive(Debug, Deserialize)]
+struct Lists {
+    #[serde(default)]
+    admins: Vec<Admin>,
+    #[serde(default)]
+    teams: Vec<Team>,
+}
+
+pub enum DirectorySource {
+    Api(IntercomClient),
+    /// A JSON file with `admins` and `teams` lists
+    File(PathBuf),
+}
+impl DirectorySource {
+    pub async fn load(&self) -> Result<Directory> {
+        match self {
+            Self::Api(client) => {
+                let parse = |path: &str, value| {
+                    serde_json::from_value::<Lists>(value).map_err(|e| Error::IntercomApi {
+                        path: path.into(),
+                        status: None,
+                        reason: e.to_string(),
+                    })
+                };
+
+                let (admins, teams) = futures::try_join!(client.admins(), client.teams())?;
+                let (admins, teams) = (parse("/admins", admins)?, parse("/teams", teams)?);
+
+                Ok(Directory::new(admins.admins, teams.teams))
+            }
+            Self::File(path) => {
+                let name = path.display().to_string();
+                let content = tokio::fs::read(path)
+                    .await
+                    .map_err(|e| Error::configuration(&name, e))?;
+                let lists: Lists =
+                    serde_json::from_slice(&content).map_err(|e| Error::configuration(&name, e))?;
+
+                Ok(Directory::new(lists.admins, lists.teams))
+            }
+        }
+    }
+}
+
+/// A directory kept across warm invocations and reloaded once it is older than `ttl`
+///
+/// When reloading fails the previous directory is used and the reload is retried on the next call
+pub struct CachedDirectory {
+    source: DirectorySource,
+    ttl: Duration,
+    cached: Mutex<Option<(Instant, Arc<Directory>)>>,
+}
+impl CachedDirectory {
+    pub fn new(source: DirectorySource, ttl: Duration) -> Self {
+        Self {
+            source,
+            ttl,
+            cached: Mutex::new(None),
+        }
+    }
+
+    pub async fn get(&self) -> Result<Arc<Directory>> {
+        // Held while loading so concurrent records share a single reload
+        let mut cached = self.cached.lock().await;
+
+        if let Some((loaded_at, directory)) = cached.as_ref() {
+            if loaded_at.elapsed() < self.ttl {
+                return Ok(directory.clone());
+            }
+        }
+
+        match (self.source.load().await, cached.take()) {
+            (Ok(directory), _) => {
+                let directory = Arc::new(directory);
+                *cached = Some((Instant::now(), directory.clone()));
+                Ok(directory)
+            }
+            (Err(e), Some((loaded_at, stale))) => {
+                tracing::warn!(error = e.to_string(), "error reloading directory");
+                // Keeps the original load time so the next record retries the reload
+                *cached = Some((loaded_at, stale.clone()));
+                Ok(stale)
+            }
+            (Err(e), None) => Err(e),
+        }
+    }
+}
@@ -0,0 +1,49 @@
+use super::{notification::Notification, timestamp, DateTime};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// [Type definition](https://developers.intercom.com/docs/references/rest-api/api.intercom.io/Admins/admin/)
+///
+/// The item of `admin.*` notifications, which only carry the fields relevant to the event
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone)]
+pub struct Admin {
+    #[serde(rename = "type")]
+    pub typ: String,
+    pub id: String,
+    pub name: Option<String>,
+    pub email: Option<String>,
+    pub job_title: Option<String>,
+    #[serde(default)]
+    pub away_mode_enabled: bool,
+    /// Whether new conversations are reassigned while the admin is away
+    #[serde(default)]
+    pub away_mode_reassign: bool,
+    pub has_inbox_seat: Option<bool>,
+    #[serde(default)]
+    pub team_ids: Vec<i64>,
+}
+
+/// Whether a teammate was available from `changed_at`, taken from `admin.away_mode_updated`
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone)]
+pub struct Availability {
+    pub admin_id: String,
+    pub away: bool,
+    pub reassign: bool,
+    #[serde(with = "timestamp")]
+    #[schemars(schema_with = "timestamp::schema")]
+    pub changed_at: DateTime,
+    pub notification_id: String,
+}
+impl Availability {
+    pub const TOPIC: &'static str = "admin.away_mode_updated";
+
+    pub fn new<T>(notification: &Notification<T>, admin: &Admin) -> Self {
+        Self {
+            admin_id: admin.id.clone(),
+            away: admin.away_mode_enabled,
+            reassign: admin.away_mode_reassign,
+            changed_at: notification.created_at,
+            notification_id: notification.id.clone(),
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use super::{contact::TagReference, timestamp, DateTime};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone)]
+pub struct Plan {
+    #[serde(rename = "type")]
+    pub typ: String,
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone)]
+pub struct Segment {
+    #[serde(rename = "type")]
+    pub typ: String,
+    pub id: String,
+    pub name: String,
+}
+impl_deserialize_from_wrapper!(Segment, segments);
+impl_deserialize_from_wrapper!(TagReference, tags);
+
+/// [Type definition](https://developers.intercom.com/docs/references/rest-api/api.intercom.io/Companies/company/)
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone)]
+pub struct Company {
+    #[serde(rename = "type")]
+    pub typ: String,
+    pub id: String,
+    pub name: String,
+    pub app_id: Option<String>,
+    /// The id the company was given by the workspace, not by Intercom
+    pub company_id: String,
+    pub plan: Option<Plan>,
+    /// Revenue from the company, truncated to whole units by Intercom
+    #[serde(default)]
+    pub monthly_spend: i64,
+    #[serde(default)]
+    pub user_count: i64,
+    #[serde(default)]
+    pub session_count: i64,
+    pub size: Option<i64>,
+    pub website: Option<String>,
+    pub industry: Option<String>,
+    #[serde(default, with = "timestamp::option")]
+    #[schemars(schema_with = "timestamp::option::schema")]
+    pub remote_created_at: Option<DateTime>,
+    #[serde(default, with = "timestamp::option")]
+    #[schemars(schema_with = "timestamp::option::schema")]
+    pub created_at: Option<DateTime>,
+    #[serde(default, with = "timestamp::option")]
+    #[schemars(schema_with = "timestamp::option::schema")]
+    pub updated_at: Option<DateTime>,
+    #[serde(default, with = "timestamp::option")]
+    #[schemars(schema_with = "timestamp::option::schema")]
+    pub last_request_at: Option<DateTime>,
+    #[serde(default)]
+    pub custom_attributes: HashMap<String, serde_json::Value>,
+    #[serde(
+        default,
+        deserialize_with = "Segment::deserialize_from_segments_wrapper"
+    )]
+    pub segments: Vec<Segment>,
+    #[serde(
+        default,
+        deserialize_with = "TagReference::deserialize_from_tags_wrapper"
+    )]
+    pub tags: Vec<TagReference>,
+}
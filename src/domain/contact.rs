@@ -0,0 +1,155 @@
+use super::{timestamp, DateTime};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    User,
+    Lead,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone)]
+pub struct Location {
+    #[serde(rename = "type")]
+    pub typ: String,
+    pub country: Option<String>,
+    pub region: Option<String>,
+    pub city: Option<String>,
+    pub country_code: Option<String>,
+    pub continent_code: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone)]
+pub struct Avatar {
+    #[serde(rename = "type")]
+    pub typ: String,
+    pub image_url: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone)]
+pub struct SocialProfile {
+    #[serde(rename = "type")]
+    pub typ: String,
+    pub name: String,
+    pub url: String,
+}
+impl_deserialize_from_wrapper!(SocialProfile, data);
+
+/// A tag, company or note the contact is attached to, without its details
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone)]
+pub struct AddressableReference {
+    #[serde(rename = "type")]
+    pub typ: String,
+    pub id: String,
+    pub url: Option<String>,
+}
+impl_deserialize_from_wrapper!(AddressableReference, data);
+
+/// [Type definition](https://developers.intercom.com/docs/references/rest-api/api.intercom.io/Contacts/contact/)
+///
+/// Notifications about deleted or archived contacts only carry a few fields,
+/// so everything other than the id is optional
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone)]
+pub struct Contact {
+    #[serde(rename = "type")]
+    pub typ: String,
+    pub id: String,
+    pub workspace_id: Option<String>,
+    pub external_id: Option<String>,
+    pub role: Option<Role>,
+    pub email: Option<String>,
+    pub email_domain: Option<String>,
+    pub phone: Option<String>,
+    pub formatted_phone: Option<String>,
+    pub name: Option<String>,
+    pub owner_id: Option<i64>,
+    #[serde(default)]
+    pub has_hard_bounced: bool,
+    #[serde(default)]
+    pub marked_email_as_spam: bool,
+    #[serde(default)]
+    pub unsubscribed_from_emails: bool,
+    #[serde(default, with = "timestamp::option")]
+    #[schemars(schema_with = "timestamp::option::schema")]
+    pub created_at: Option<DateTime>,
+    #[serde(default, with = "timestamp::option")]
+    #[schemars(schema_with = "timestamp::option::schema")]
+    pub updated_at: Option<DateTime>,
+    #[serde(default, with = "timestamp::option")]
+    #[schemars(schema_with = "timestamp::option::schema")]
+    pub signed_up_at: Option<DateTime>,
+    #[serde(default, with = "timestamp::option")]
+    #[schemars(schema_with = "timestamp::option::schema")]
+    pub last_seen_at: Option<DateTime>,
+    #[serde(default, with = "timestamp::option")]
+    #[schemars(schema_with = "timestamp::option::schema")]
+    pub last_replied_at: Option<DateTime>,
+    #[serde(default, with = "timestamp::option")]
+    #[schemars(schema_with = "timestamp::option::schema")]
+    pub last_contacted_at: Option<DateTime>,
+    #[serde(default, with = "timestamp::option")]
+    #[schemars(schema_with = "timestamp::option::schema")]
+    pub last_email_opened_at: Option<DateTime>,
+    #[serde(default, with = "timestamp::option")]
+    #[schemars(schema_with = "timestamp::option::schema")]
+    pub last_email_clicked_at: Option<DateTime>,
+    pub language_override: Option<String>,
+    pub browser: Option<String>,
+    pub browser_version: Option<String>,
+    pub browser_language: Option<String>,
+    pub os: Option<String>,
+    pub location: Option<Location>,
+    pub avatar: Option<Avatar>,
+    #[serde(default)]
+    pub custom_attributes: HashMap<String, serde_json::Value>,
+    #[serde(
+        default,
+        deserialize_with = "AddressableReference::deserialize_from_data_wrapper"
+    )]
+    pub tags: Vec<AddressableReference>,
+    #[serde(
+        default,
+        deserialize_with = "AddressableReference::deserialize_from_data_wrapper"
+    )]
+    pub companies: Vec<AddressableReference>,
+    #[serde(
+        default,
+        deserialize_with = "SocialProfile::deserialize_from_data_wrapper"
+    )]
+    pub social_profiles: Vec<SocialProfile>,
+}
+impl Contact {
+    /// Fields that identify or locate a person
+    pub const PII_FIELDS: &'static [&'static str] = &[
+        "email",
+        "email_domain",
+        "phone",
+        "formatted_phone",
+        "name",
+        "location",
+        "avatar",
+        "social_profiles",
+    ];
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone)]
+pub struct TagReference {
+    #[serde(rename = "type")]
+    pub typ: String,
+    pub id: String,
+    pub name: String,
+}
+
+/// The item of `contact.*.tag.*` notifications
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone)]
+pub struct ContactTag {
+    #[serde(rename = "type")]
+    pub typ: String,
+    #[serde(with = "timestamp")]
+    #[schemars(schema_with = "timestamp::schema")]
+    pub created_at: DateTime,
+    pub tag: TagReference,
+    pub contact: Contact,
+}
@@ -1,48 +1,21 @@
-use super::DateTime;
-use chrono::serde::{ts_seconds, ts_seconds_option};
-use paste::paste;
-use serde::{Deserialize, Deserializer, Serialize};
-use std::collections::HashMap;
+use super::{timestamp, DateTime};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fmt};
 
-/// Generate a function that extracts an array from an object with a type field
-/// and the array of name `$field`
-///
-/// The name of the generated function is `deserialize_from_<$field>_wrapper`
-macro_rules! impl_deserialize_from_wrapper {
-    ($typ: ty, $field: ident) => {
-        impl $typ {
-            paste! {
-                pub(crate) fn [<deserialize_from_ $field _wrapper>]<'de, D>(de: D) -> Result<Vec<Self>, D::Error>
-                where
-                    D: Deserializer<'de>,
-                {
-                    #[derive(Deserialize)]
-                    struct Wrapper {
-                        $field: Vec<$typ>,
-                    }
-
-                    match Wrapper::deserialize(de) {
-                        Ok(wrapper) => Ok(wrapper.$field),
-                        Err(_) => Ok(vec![]),
-                    }
-                }
-            }
-        }
-    };
-}
-
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone)]
 pub struct Tag {
     #[serde(rename = "type")]
     pub typ: String,
     pub id: String,
     pub name: String,
-    #[serde(deserialize_with = "ts_seconds::deserialize")]
+    #[serde(with = "timestamp")]
+    #[schemars(schema_with = "timestamp::schema")]
     pub applied_at: DateTime,
 }
 impl_deserialize_from_wrapper!(Tag, tags);
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone)]
 pub struct Reference {
     #[serde(rename = "type")]
     pub typ: String,
@@ -50,7 +23,7 @@ pub struct Reference {
 }
 impl_deserialize_from_wrapper!(Reference, teammates);
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone)]
 pub struct ContactReference {
     #[serde(flatten)]
     pub reference: Reference,
@@ -58,7 +31,7 @@ pub struct ContactReference {
 }
 impl_deserialize_from_wrapper!(ContactReference, contacts);
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone)]
 #[serde(rename_all = "snake_case")]
 pub enum ConversationState {
     Open,
@@ -66,33 +39,36 @@ pub enum ConversationState {
     Snoozed,
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone)]
 #[serde(rename_all = "snake_case")]
 pub enum ConversationPriority {
     Priority,
     NotPriority,
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone)]
 pub struct ConversationRating {
     pub rating: i8,
     pub remark: String,
-    #[serde(deserialize_with = "ts_seconds::deserialize")]
+    #[serde(with = "timestamp")]
+    #[schemars(schema_with = "timestamp::schema")]
     pub created_at: DateTime,
     pub contact: ContactReference,
     pub teammate: Reference,
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone)]
 pub struct Author {
     #[serde(rename = "type")]
     pub typ: String,
     pub id: String,
     pub name: String,
-    pub email: String,
+    /// Bots, workflows and other automations have none
+    #[serde(default)]
+    pub email: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone)]
 pub struct Attachment {
     #[serde(rename = "type")]
     pub typ: String,
@@ -104,7 +80,7 @@ pub struct Attachment {
     pub height: i32,
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone)]
 #[serde(rename_all = "snake_case")]
 pub enum DeliveredAs {
     CustomerInitiated,
@@ -114,7 +90,7 @@ pub enum DeliveredAs {
     AdminInitiated,
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone)]
 pub struct ConversationSource {
     #[serde(rename = "type")]
     pub typ: String,
@@ -132,16 +108,41 @@ pub struct ConversationSource {
     pub redacted: bool,
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+/// A message or action in a conversation, kept without its body, author and attachments
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone)]
+pub struct ConversationPart {
+    #[serde(rename = "type")]
+    pub typ: String,
+    pub id: String,
+    pub part_type: String,
+    #[serde(skip_serializing)]
+    pub body: Option<String>,
+    #[serde(with = "timestamp")]
+    #[schemars(schema_with = "timestamp::schema")]
+    pub created_at: DateTime,
+    #[serde(with = "timestamp")]
+    #[schemars(schema_with = "timestamp::schema")]
+    pub updated_at: DateTime,
+    #[serde(default, with = "timestamp::option")]
+    #[schemars(schema_with = "timestamp::option::schema")]
+    pub notified_at: Option<DateTime>,
+    pub assigned_to: Option<Reference>,
+    pub external_id: Option<String>,
+    pub redacted: bool,
+}
+impl_deserialize_from_wrapper!(ConversationPart, conversation_parts);
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone)]
 pub struct FirstContactReply {
-    #[serde(deserialize_with = "ts_seconds::deserialize")]
+    #[serde(with = "timestamp")]
+    #[schemars(schema_with = "timestamp::schema")]
     pub created_at: DateTime,
     #[serde(rename = "type")]
     pub typ: String,
     pub url: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone)]
 #[serde(rename_all = "snake_case")]
 pub enum SLAStatus {
     Hit,
@@ -149,8 +150,19 @@ pub enum SLAStatus {
     Cancelled,
     Active,
 }
+impl fmt::Display for SLAStatus {
+    /// As the status is serialized
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Hit => "hit",
+            Self::Missed => "missed",
+            Self::Cancelled => "cancelled",
+            Self::Active => "active",
+        })
+    }
+}
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone)]
 pub struct AppliedSLA {
     #[serde(rename = "type")]
     pub typ: String,
@@ -158,7 +170,7 @@ pub struct AppliedSLA {
     pub sla_status: SLAStatus,
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone)]
 pub struct Statistics {
     #[serde(rename = "type")]
     pub typ: String,
@@ -167,23 +179,32 @@ pub struct Statistics {
     pub time_to_first_close: u32,
     pub time_to_last_close: u32,
     pub median_time_to_reply: u32,
-    #[serde(deserialize_with = "ts_seconds::deserialize")]
+    #[serde(with = "timestamp")]
+    #[schemars(schema_with = "timestamp::schema")]
     pub first_contact_reply_at: DateTime,
-    #[serde(deserialize_with = "ts_seconds::deserialize")]
+    #[serde(with = "timestamp")]
+    #[schemars(schema_with = "timestamp::schema")]
     pub first_assignment_at: DateTime,
-    #[serde(deserialize_with = "ts_seconds::deserialize")]
+    #[serde(with = "timestamp")]
+    #[schemars(schema_with = "timestamp::schema")]
     pub first_admin_reply_at: DateTime,
-    #[serde(deserialize_with = "ts_seconds::deserialize")]
+    #[serde(with = "timestamp")]
+    #[schemars(schema_with = "timestamp::schema")]
     pub first_close_at: DateTime,
-    #[serde(deserialize_with = "ts_seconds::deserialize")]
+    #[serde(with = "timestamp")]
+    #[schemars(schema_with = "timestamp::schema")]
     pub last_assignment_at: DateTime,
-    #[serde(deserialize_with = "ts_seconds::deserialize")]
+    #[serde(with = "timestamp")]
+    #[schemars(schema_with = "timestamp::schema")]
     pub last_assignment_admin_reply_at: DateTime,
-    #[serde(deserialize_with = "ts_seconds::deserialize")]
+    #[serde(with = "timestamp")]
+    #[schemars(schema_with = "timestamp::schema")]
     pub last_contact_reply_at: DateTime,
-    #[serde(deserialize_with = "ts_seconds::deserialize")]
+    #[serde(with = "timestamp")]
+    #[schemars(schema_with = "timestamp::schema")]
     pub last_admin_reply_at: DateTime,
-    #[serde(deserialize_with = "ts_seconds::deserialize")]
+    #[serde(with = "timestamp")]
+    #[schemars(schema_with = "timestamp::schema")]
     pub last_close_at: DateTime,
     pub last_closed_by_id: String,
     pub count_reopens: i32,
@@ -191,7 +212,7 @@ pub struct Statistics {
     pub count_conversation_parts: i32,
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone)]
 #[serde(rename_all = "snake_case")]
 pub enum ContentType {
     File,
@@ -201,7 +222,7 @@ pub enum ContentType {
     WorkflowConnectorAction,
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone)]
 pub struct ContentSources {
     pub content_type: ContentType,
     pub url: String,
@@ -210,7 +231,7 @@ pub struct ContentSources {
 }
 impl_deserialize_from_wrapper!(ContentSources, content_sources);
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone)]
 #[serde(rename_all = "snake_case")]
 pub enum SourceType {
     EssentialsPlanSetup,
@@ -220,7 +241,7 @@ pub enum SourceType {
     FinPreview,
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone)]
 #[serde(rename_all = "snake_case")]
 pub enum LastAnswerType {
     #[serde(rename = "ai_answer")]
@@ -228,7 +249,7 @@ pub enum LastAnswerType {
     CustomAnswer,
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone)]
 #[serde(rename_all = "snake_case")]
 pub enum ResolutionState {
     AssumedResolution,
@@ -237,7 +258,7 @@ pub enum ResolutionState {
     Abandoned,
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone)]
 pub struct AIAgent {
     pub source_type: SourceType,
     pub source_title: Option<String>,
@@ -253,19 +274,23 @@ pub struct AIAgent {
 }
 
 /// [Type definition](https://developers.intercom.com/docs/references/rest-api/api.intercom.io/Conversations/conversation/)
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone)]
 pub struct Conversation {
     #[serde(rename = "type")]
     pub typ: String,
     pub id: String,
     pub title: Option<String>,
-    #[serde(deserialize_with = "ts_seconds::deserialize")]
+    #[serde(with = "timestamp")]
+    #[schemars(schema_with = "timestamp::schema")]
     pub created_at: DateTime,
-    #[serde(deserialize_with = "ts_seconds::deserialize")]
+    #[serde(with = "timestamp")]
+    #[schemars(schema_with = "timestamp::schema")]
     pub updated_at: DateTime,
-    #[serde(deserialize_with = "ts_seconds_option::deserialize")]
+    #[serde(with = "timestamp::option")]
+    #[schemars(schema_with = "timestamp::option::schema")]
     pub waiting_since: Option<DateTime>,
-    #[serde(deserialize_with = "ts_seconds_option::deserialize")]
+    #[serde(with = "timestamp::option")]
+    #[schemars(schema_with = "timestamp::option::schema")]
     pub snoozed_until: Option<DateTime>,
     pub open: bool,
     pub state: ConversationState,
@@ -281,6 +306,12 @@ pub struct Conversation {
     pub contacts: Vec<ContactReference>,
     #[serde(deserialize_with = "Reference::deserialize_from_teammates_wrapper")]
     pub teammates: Vec<Reference>,
+    /// Webhooks may carry only the latest parts, enrichment fetches all of them
+    #[serde(
+        default,
+        deserialize_with = "ConversationPart::deserialize_from_conversation_parts_wrapper"
+    )]
+    pub conversation_parts: Vec<ConversationPart>,
     pub custom_attributes: HashMap<String, String>,
     pub first_contact_reply: Option<FirstContactReply>,
     pub sla_applied: Option<AppliedSLA>,
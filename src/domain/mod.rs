@@ -1,6 +1,80 @@
+use schemars::JsonSchema;
+use serde::Serialize;
+
+/// Generate a function that extracts an array from an object with a type field
+/// and the array of name `$field`
+///
+/// The name of the generated function is `deserialize_from_<$field>_wrapper`
+macro_rules! impl_deserialize_from_wrapper {
+    ($typ: ty, $field: ident) => {
+        impl $typ {
+            paste::paste! {
+                pub(crate) fn [<deserialize_from_ $field _wrapper>]<'de, D>(de: D) -> Result<Vec<Self>, D::Error>
+                where
+                    D: serde::Deserializer<'de>,
+                {
+                    #[derive(serde::Deserialize)]
+                    struct Wrapper {
+                        $field: Vec<$typ>,
+                    }
+
+                    match <Wrapper as serde::Deserialize>::deserialize(de) {
+                        Ok(wrapper) => Ok(wrapper.$field),
+                        Err(_) => Ok(vec![]),
+                    }
+                }
+            }
+        }
+    };
+}
+
+pub mod admin;
+pub mod company;
+pub mod contact;
 pub mod conversation;
 pub mod notification;
+pub mod ping;
+pub mod ticket;
+pub mod timestamp;
+pub mod topic;
+
+use admin::Admin;
+use company::Company;
+use contact::{Contact, ContactTag};
+use conversation::Conversation;
+use ticket::Ticket;
 
 /// NOTE: Intercom provides times as epoch time in seconds
 /// https://www.intercom.com/help/en/articles/3605703-how-dates-work-in-intercom
 pub type DateTime = chrono::DateTime<chrono::Utc>;
+
+/// The object a notification is about, serialized as the object itself
+#[derive(Debug, Serialize, JsonSchema, PartialEq, Eq, Clone)]
+#[serde(untagged)]
+pub enum Item {
+    Conversation(Box<Conversation>),
+    Ticket(Box<Ticket>),
+    Contact(Box<Contact>),
+    ContactTag(Box<ContactTag>),
+    Company(Box<Company>),
+    Admin(Box<Admin>),
+}
+impl Item {
+    pub fn id(&self) -> &str {
+        match self {
+            Self::Conversation(conversation) => &conversation.id,
+            Self::Ticket(ticket) => &ticket.id,
+            Self::Contact(contact) => &contact.id,
+            Self::ContactTag(contact_tag) => &contact_tag.contact.id,
+            Self::Company(company) => &company.id,
+            Self::Admin(admin) => &admin.id,
+        }
+    }
+
+    pub fn as_conversation(&self) -> Option<&Conversation> {
+        match self {
+            Self::Conversation(conversation) => Some(conversation),
+            _ => None,
+        }
+    }
+}
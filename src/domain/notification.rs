@@ -1,5 +1,5 @@
-use super::DateTime;
-use chrono::serde::ts_seconds;
+use super::{timestamp, topic::Topic, DateTime};
+use schemars::JsonSchema;
 use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize};
 
 #[derive(Deserialize)]
@@ -19,19 +19,39 @@ where
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
-pub struct Notification<T: DeserializeOwned> {
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone)]
+#[serde(bound(deserialize = "T: DeserializeOwned"))]
+pub struct Notification<T> {
     #[serde(rename = "type")]
     pub typ: String,
     pub id: String,
+    /// The workspace the notification is from
+    pub app_id: String,
     #[serde(rename = "self")]
     pub url: Option<String>,
-    #[serde(deserialize_with = "ts_seconds::deserialize")]
+    #[serde(with = "timestamp")]
+    #[schemars(schema_with = "timestamp::schema")]
     pub created_at: DateTime,
-    pub topic: String,
+    pub topic: Topic,
     pub delivery_attempts: i32,
-    #[serde(deserialize_with = "ts_seconds::deserialize")]
+    #[serde(with = "timestamp")]
+    #[schemars(schema_with = "timestamp::schema")]
     pub first_sent_at: DateTime,
     #[serde(deserialize_with = "Data::deserialize_item")]
     pub data: T,
 }
+impl<T> Notification<T> {
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> Notification<U> {
+        Notification {
+            typ: self.typ,
+            id: self.id,
+            app_id: self.app_id,
+            url: self.url,
+            created_at: self.created_at,
+            topic: self.topic,
+            delivery_attempts: self.delivery_attempts,
+            first_sent_at: self.first_sent_at,
+            data: f(self.data),
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use super::{timestamp, DateTime};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// The item of `ping` notifications
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone)]
+pub struct Ping {
+    #[serde(rename = "type")]
+    pub typ: String,
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Eq, Clone)]
+struct PingData {
+    item: Ping,
+}
+
+/// Sent when a webhook subscription is created or tested, unlike other notifications
+/// it has no id and its item is not an Intercom object
+#[derive(Debug, Deserialize, PartialEq, Eq, Clone)]
+pub struct PingNotification {
+    pub app_id: Option<String>,
+    #[serde(deserialize_with = "timestamp::deserialize")]
+    pub created_at: DateTime,
+    #[serde(deserialize_with = "PingNotification::deserialize_item")]
+    pub data: Ping,
+}
+impl PingNotification {
+    fn deserialize_item<'de, D>(de: D) -> Result<Ping, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        PingData::deserialize(de).map(|x| x.item)
+    }
+}
+
+/// Written for each ping, so a workspace whose pings stop arriving can be noticed
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone)]
+pub struct Heartbeat {
+    pub app_id: Option<String>,
+    pub message: Option<String>,
+    #[serde(with = "timestamp")]
+    #[schemars(schema_with = "timestamp::schema")]
+    pub sent_at: DateTime,
+    #[serde(with = "timestamp")]
+    #[schemars(schema_with = "timestamp::schema")]
+    pub received_at: DateTime,
+}
+impl Heartbeat {
+    pub fn new(ping: &PingNotification, received_at: DateTime) -> Self {
+        Self {
+            app_id: ping.app_id.clone(),
+            message: ping.data.message.clone(),
+            sent_at: ping.created_at,
+            received_at,
+        }
+    }
+}
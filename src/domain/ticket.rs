@@ -0,0 +1,160 @@
+use super::{
+    conversation::{Author, ContactReference, Reference},
+    timestamp, DateTime,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone)]
+pub enum TicketCategory {
+    Customer,
+    #[serde(rename = "Back-office")]
+    BackOffice,
+    Tracker,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum TicketStateCategory {
+    Submitted,
+    InProgress,
+    WaitingOnCustomer,
+    Resolved,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone)]
+pub struct TicketState {
+    #[serde(rename = "type")]
+    pub typ: String,
+    pub id: String,
+    pub category: TicketStateCategory,
+    /// Shown to teammates
+    pub internal_label: String,
+    /// Shown to contacts
+    pub external_label: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone)]
+pub struct TicketTypeAttribute {
+    #[serde(rename = "type")]
+    pub typ: String,
+    pub id: String,
+    pub workspace_id: String,
+    pub name: String,
+    pub description: String,
+    /// `string`, `list`, `integer`, `decimal`, `boolean`, `datetime` or `files`
+    pub data_type: String,
+    pub input_options: Option<serde_json::Value>,
+    pub order: i32,
+    pub required_to_create: bool,
+    pub required_to_create_for_contacts: bool,
+    pub visible_on_create: bool,
+    pub visible_to_contacts: bool,
+    pub default: bool,
+    pub ticket_type_id: i32,
+    pub archived: bool,
+    #[serde(with = "timestamp")]
+    #[schemars(schema_with = "timestamp::schema")]
+    pub created_at: DateTime,
+    #[serde(with = "timestamp")]
+    #[schemars(schema_with = "timestamp::schema")]
+    pub updated_at: DateTime,
+}
+impl_deserialize_from_wrapper!(TicketTypeAttribute, data);
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone)]
+pub struct TicketType {
+    #[serde(rename = "type")]
+    pub typ: String,
+    pub id: String,
+    pub category: TicketCategory,
+    pub name: String,
+    pub description: String,
+    pub icon: String,
+    pub workspace_id: String,
+    #[serde(deserialize_with = "TicketTypeAttribute::deserialize_from_data_wrapper")]
+    pub ticket_type_attributes: Vec<TicketTypeAttribute>,
+    pub archived: bool,
+    #[serde(with = "timestamp")]
+    #[schemars(schema_with = "timestamp::schema")]
+    pub created_at: DateTime,
+    #[serde(with = "timestamp")]
+    #[schemars(schema_with = "timestamp::schema")]
+    pub updated_at: DateTime,
+}
+
+/// A ticket or conversation linked to the ticket
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone)]
+pub struct LinkedObject {
+    /// `ticket` or `conversation`
+    #[serde(rename = "type")]
+    pub typ: String,
+    pub id: String,
+    /// Only set for tickets
+    pub category: Option<TicketCategory>,
+}
+impl_deserialize_from_wrapper!(LinkedObject, data);
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone)]
+pub struct TicketPart {
+    #[serde(rename = "type")]
+    pub typ: String,
+    pub id: String,
+    pub part_type: String,
+    #[serde(skip_serializing)]
+    pub body: Option<String>,
+    pub previous_ticket_state: Option<TicketStateCategory>,
+    pub ticket_state: TicketStateCategory,
+    #[serde(with = "timestamp")]
+    #[schemars(schema_with = "timestamp::schema")]
+    pub created_at: DateTime,
+    #[serde(with = "timestamp")]
+    #[schemars(schema_with = "timestamp::schema")]
+    pub updated_at: DateTime,
+    pub assigned_to: Option<Reference>,
+    #[serde(skip_serializing)]
+    pub author: Author,
+    pub redacted: bool,
+}
+impl_deserialize_from_wrapper!(TicketPart, ticket_parts);
+
+/// [Type definition](https://developers.intercom.com/docs/references/rest-api/api.intercom.io/Tickets/ticket/)
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone)]
+pub struct Ticket {
+    #[serde(rename = "type")]
+    pub typ: String,
+    pub id: String,
+    /// The id shown to teammates and contacts
+    pub ticket_id: String,
+    pub category: TicketCategory,
+    /// Values of the ticket type attributes, by attribute name
+    pub ticket_attributes: HashMap<String, serde_json::Value>,
+    pub ticket_state: TicketState,
+    pub ticket_type: TicketType,
+    #[serde(deserialize_with = "ContactReference::deserialize_from_contacts_wrapper")]
+    pub contacts: Vec<ContactReference>,
+    pub admin_assignee_id: Option<String>,
+    pub team_assignee_id: Option<String>,
+    #[serde(with = "timestamp")]
+    #[schemars(schema_with = "timestamp::schema")]
+    pub created_at: DateTime,
+    #[serde(with = "timestamp")]
+    #[schemars(schema_with = "timestamp::schema")]
+    pub updated_at: DateTime,
+    pub open: bool,
+    #[serde(default, with = "timestamp::option")]
+    #[schemars(schema_with = "timestamp::option::schema")]
+    pub snoozed_until: Option<DateTime>,
+    #[serde(
+        default,
+        deserialize_with = "LinkedObject::deserialize_from_data_wrapper"
+    )]
+    pub linked_objects: Vec<LinkedObject>,
+    #[serde(
+        default,
+        deserialize_with = "TicketPart::deserialize_from_ticket_parts_wrapper"
+    )]
+    pub ticket_parts: Vec<TicketPart>,
+    pub is_shared: bool,
+}
@@ -0,0 +1,134 @@
+//! Timestamps are written in the configured [`TimestampFormat`] and read back in it,
+//! used with `#[serde(with = "timestamp")]` or `"timestamp::option"`
+//!
+//! The format is set for the duration of [`TimestampFormat::scope`], since serde gives no way of
+//! passing it down to the fields. Outside of a scope timestamps are written in RFC 3339, and
+//! integers are read as epoch seconds and strings as RFC 3339, so both Intercom's notifications
+//! and objects written with the default format can be read.
+
+use std::{cell::Cell, fmt};
+
+use chrono::serde::{ts_milliseconds, ts_milliseconds_option, ts_seconds, ts_seconds_option};
+use schemars::{json_schema, JsonSchema, Schema, SchemaGenerator};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use super::DateTime;
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum TimestampFormat {
+    /// Whole seconds since the epoch, as Intercom sends them
+    EpochSeconds,
+    EpochMillis,
+    #[default]
+    Rfc3339,
+}
+
+thread_local! {
+    static SCOPE: Cell<Option<TimestampFormat>> = const { Cell::new(None) };
+}
+
+impl TimestampFormat {
+    /// Run `f` with timestamps written, and read, in this format
+    pub fn scope<R>(self, f: impl FnOnce() -> R) -> R {
+        struct Restore(Option<TimestampFormat>);
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                SCOPE.set(self.0);
+            }
+        }
+
+        let _restore = Restore(SCOPE.replace(Some(self)));
+        f()
+    }
+
+    fn written() -> Self {
+        SCOPE.get().unwrap_or_default()
+    }
+
+    fn schema(self) -> (&'static str, Option<&'static str>) {
+        match self {
+            Self::EpochSeconds | Self::EpochMillis => ("integer", None),
+            Self::Rfc3339 => ("string", Some("date-time")),
+        }
+    }
+}
+
+pub fn serialize<S: Serializer>(value: &DateTime, serializer: S) -> Result<S::Ok, S::Error> {
+    match TimestampFormat::written() {
+        TimestampFormat::EpochSeconds => ts_seconds::serialize(value, serializer),
+        TimestampFormat::EpochMillis => ts_milliseconds::serialize(value, serializer),
+        TimestampFormat::Rfc3339 => value.serialize(serializer),
+    }
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(de: D) -> Result<DateTime, D::Error> {
+    match SCOPE.get() {
+        None => de.deserialize_any(AnyVisitor),
+        Some(TimestampFormat::EpochSeconds) => ts_seconds::deserialize(de),
+        Some(TimestampFormat::EpochMillis) => ts_milliseconds::deserialize(de),
+        Some(TimestampFormat::Rfc3339) => DateTime::deserialize(de),
+    }
+}
+
+/// Epoch seconds or RFC 3339, for reading outside of a scope
+struct AnyVisitor;
+impl de::Visitor<'_> for AnyVisitor {
+    type Value = DateTime;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a unix timestamp in seconds or an RFC 3339 date and time")
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<DateTime, E> {
+        DateTime::from_timestamp(value, 0)
+            .ok_or_else(|| E::custom(format!("timestamp out of range: {value}")))
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<DateTime, E> {
+        i64::try_from(value)
+            .map_err(|_| E::custom(format!("timestamp out of range: {value}")))
+            .and_then(|x| self.visit_i64(x))
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<DateTime, E> {
+        value.parse().map_err(E::custom)
+    }
+}
+
+/// The schema of timestamps as they are written in the current scope
+pub fn schema(_: &mut SchemaGenerator) -> Schema {
+    match TimestampFormat::written().schema() {
+        (typ, Some(format)) => json_schema!({ "type": typ, "format": format }),
+        (typ, None) => json_schema!({ "type": typ }),
+    }
+}
+
+pub mod option {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        value: &Option<DateTime>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match TimestampFormat::written() {
+            TimestampFormat::EpochSeconds => ts_seconds_option::serialize(value, serializer),
+            TimestampFormat::EpochMillis => ts_milliseconds_option::serialize(value, serializer),
+            TimestampFormat::Rfc3339 => value.serialize(serializer),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(de: D) -> Result<Option<DateTime>, D::Error> {
+        #[derive(Deserialize)]
+        struct Timestamp(#[serde(deserialize_with = "super::deserialize")] DateTime);
+
+        Option::<Timestamp>::deserialize(de).map(|x| x.map(|Timestamp(x)| x))
+    }
+
+    pub fn schema(_: &mut SchemaGenerator) -> Schema {
+        match TimestampFormat::written().schema() {
+            (typ, Some(format)) => json_schema!({ "type": [typ, "null"], "format": format }),
+            (typ, None) => json_schema!({ "type": [typ, "null"] }),
+        }
+    }
+}
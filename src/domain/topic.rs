@@ -0,0 +1,258 @@
+use crate::utils::glob_match;
+use schemars::{json_schema, JsonSchema, Schema, SchemaGenerator};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{convert::Infallible, fmt, str::FromStr};
+
+/// What a topic is about, the item type of its notifications
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Object {
+    Admin,
+    Company,
+    Contact,
+    ContentStat,
+    Conversation,
+    ConversationPart,
+    Event,
+    Job,
+    Ping,
+    Ticket,
+    Visitor,
+}
+impl Object {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Admin => "admin",
+            Self::Company => "company",
+            Self::Contact => "contact",
+            Self::ContentStat => "content_stat",
+            Self::Conversation => "conversation",
+            Self::ConversationPart => "conversation_part",
+            Self::Event => "event",
+            Self::Job => "job",
+            Self::Ping => "ping",
+            Self::Ticket => "ticket",
+            Self::Visitor => "visitor",
+        }
+    }
+
+    /// Whether the handler has a typed model of the items of this object's notifications
+    pub fn is_modelled(&self) -> bool {
+        matches!(
+            self,
+            Self::Admin | Self::Company | Self::Contact | Self::Conversation | Self::Ticket
+        )
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        [
+            Self::Admin,
+            Self::Company,
+            Self::Contact,
+            Self::ContentStat,
+            Self::Conversation,
+            Self::ConversationPart,
+            Self::Event,
+            Self::Job,
+            Self::Ping,
+            Self::Ticket,
+            Self::Visitor,
+        ]
+        .into_iter()
+        .find(|x| x.as_str() == s)
+    }
+}
+
+/// Who acted, or for contacts their role, such as `admin` in `conversation.admin.closed`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Actor {
+    Admin,
+    Contact,
+    Lead,
+    Operator,
+    Team,
+    User,
+}
+impl Actor {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Admin => "admin",
+            Self::Contact => "contact",
+            Self::Lead => "lead",
+            Self::Operator => "operator",
+            Self::Team => "team",
+            Self::User => "user",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        [
+            Self::Admin,
+            Self::Contact,
+            Self::Lead,
+            Self::Operator,
+            Self::Team,
+            Self::User,
+        ]
+        .into_iter()
+        .find(|x| x.as_str() == s)
+    }
+}
+
+/// A notification topic such as `conversation.admin.closed`, parsed into its object,
+/// the actor when there is one, and the action, the rest of the topic
+///
+/// Topics about objects the handler does not know, or with unexpected characters, are kept
+/// as they were sent. Either way the topic displays and serializes as the original string
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Topic {
+    Known {
+        object: Object,
+        actor: Option<Actor>,
+        /// Empty for `ping`, which has no action
+        action: String,
+    },
+    Unknown(String),
+}
+impl Topic {
+    pub fn object(&self) -> Option<Object> {
+        match self {
+            Self::Known { object, .. } => Some(*object),
+            Self::Unknown(_) => None,
+        }
+    }
+
+    pub fn action(&self) -> Option<&str> {
+        match self {
+            Self::Known { action, .. } => Some(action),
+            Self::Unknown(_) => None,
+        }
+    }
+
+    /// Match a glob pattern from the configuration, `*` matches any run of characters
+    pub fn matches(&self, pattern: &str) -> bool {
+        glob_match(pattern, &self.to_string())
+    }
+
+    /// A low cardinality label for metrics, the object or `unknown`
+    pub fn label(&self) -> &'static str {
+        self.object().map_or("unknown", |x| x.as_str())
+    }
+
+    /// The topic with characters other than letters, digits, `.`, `_` and `-` replaced by `_`,
+    /// only unknown topics can contain them
+    pub fn sanitized(&self) -> String {
+        self.to_string()
+            .chars()
+            .map(|c| match c {
+                'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '_' | '-' => c,
+                _ => '_',
+            })
+            .collect()
+    }
+}
+impl FromStr for Topic {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let unknown = || Ok(Self::Unknown(s.into()));
+
+        let valid = |segment: &str| {
+            !segment.is_empty()
+                && segment
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+        };
+        if !s.split('.').all(valid) {
+            return unknown();
+        }
+
+        let (object, rest) = s.split_once('.').unwrap_or((s, ""));
+        let Some(object) = Object::parse(object) else {
+            return unknown();
+        };
+
+        let (actor, action) = match rest.split_once('.') {
+            Some((actor, action)) => match Actor::parse(actor) {
+                Some(actor) => (Some(actor), action),
+                None => (None, rest),
+            },
+            None => (None, rest),
+        };
+        if action.is_empty() != (object == Object::Ping) {
+            return unknown();
+        }
+
+        Ok(Self::Known {
+            object,
+            actor,
+            action: action.into(),
+        })
+    }
+}
+impl From<&str> for Topic {
+    fn from(s: &str) -> Self {
+        match s.parse() {
+            Ok(topic) => topic,
+            Err(infallible) => match infallible {},
+        }
+    }
+}
+impl fmt::Display for Topic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Known {
+                object,
+                actor,
+                action,
+            } => {
+                f.write_str(object.as_str())?;
+                if let Some(actor) = actor {
+                    write!(f, ".{}", actor.as_str())?;
+                }
+                if !action.is_empty() {
+                    write!(f, ".{action}")?;
+                }
+                Ok(())
+            }
+            Self::Unknown(topic) => f.write_str(topic),
+        }
+    }
+}
+impl PartialEq<str> for Topic {
+    fn eq(&self, other: &str) -> bool {
+        other.parse().as_ref() == Ok(self)
+    }
+}
+impl PartialEq<&str> for Topic {
+    fn eq(&self, other: &&str) -> bool {
+        self == *other
+    }
+}
+impl Serialize for Topic {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+impl<'de> Deserialize<'de> for Topic {
+    fn deserialize<D: Deserializer<'de>>(de: D) -> Result<Self, D::Error> {
+        let topic = String::deserialize(de)?;
+        Ok(Self::from(topic.as_str()))
+    }
+}
+/// Topics are written as the string Intercom sent, unknown topics included
+impl JsonSchema for Topic {
+    fn schema_name() -> std::borrow::Cow<'static, str> {
+        "Topic".into()
+    }
+
+    fn inline_schema() -> bool {
+        true
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        json_schema!({
+            "type": "string",
+            "description": "Notification topic such as `conversation.user.created`",
+        })
+    }
+}
@@ -0,0 +1,80 @@
+//! The shape of the objects written for each record
+//!
+//! Every record is written wrapped in an [`Envelope`], whose `schema_version` says which
+//! shape the wrapped notification has.
+//!
+//! # Compatibility policy
+//!
+//! Within a schema version consumers can rely on every field keeping its name, type and
+//! meaning. Changes that do not break that, such as adding fields, new enum values or new
+//! topics, are made without a new version, so consumers should ignore fields they do not know.
+//!
+//! `schema_version` is incremented for anything else, such as removing, renaming or retyping a
+//! field, changing the format of a value, or redacting a field by default. Timestamps are the
+//! exception, the deployment chooses their format and `timestamp_format` says which was used,
+//! so consumers read it rather than assuming one. The handler only
+//! writes the latest version and older versions are documented below for reading old objects.
+//!
+//! Each version's shape is pinned by a test against a committed fixture, so an accidental
+//! change fails the tests. Changing a fixture means either the change is additive or the
+//! version has to be incremented, with a new fixture for the new version.
+//!
+//! JSON Schemas of everything the handler writes are committed under `schemas/`, generated
+//! from the types as they are serialized with the default timestamps. A test fails when they
+//! fall out of date, running the tests with `UPDATE_SCHEMAS=1` regenerates them.
+//!
+//! # Versions
+//!
+//! 1. The notification as received with typed items, timestamps in the format named by
+//!    `timestamp_format`, RFC 3339 when it is absent
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::domain::{
+    timestamp::{self, TimestampFormat},
+    topic::Topic,
+    DateTime,
+};
+
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// The version of the handler that wrote an object
+pub const HANDLER_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone)]
+pub struct Envelope<T> {
+    pub schema_version: u32,
+    pub handler_version: String,
+    /// When the handler started processing the record
+    #[serde(with = "timestamp")]
+    #[schemars(schema_with = "timestamp::schema")]
+    pub received_at: DateTime,
+    /// How every timestamp in the object is written, objects written before it was
+    /// recorded use RFC 3339
+    #[serde(default)]
+    pub timestamp_format: TimestampFormat,
+    /// Id of the SQS message that carried the notification
+    pub message_id: Option<String>,
+    pub source_topic: Topic,
+    pub notification: T,
+}
+impl<T> Envelope<T> {
+    pub fn new(
+        received_at: DateTime,
+        timestamp_format: TimestampFormat,
+        message_id: Option<String>,
+        source_topic: Topic,
+        notification: T,
+    ) -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            handler_version: HANDLER_VERSION.into(),
+            received_at,
+            timestamp_format,
+            message_id,
+            source_topic,
+            notification,
+        }
+    }
+}
@@ -1,4 +1,159 @@
-#[derive(Debug)]
+use serde_path_to_error::{Path, Segment};
+use thiserror::Error;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Debug, Error)]
 pub enum Error {
+    #[error("invalid configuration for {name}: {reason}")]
+    Configuration { name: String, reason: String },
+
+    #[error("failed to deserialize record at {path}, found {found}: {source}")]
+    Deserialization {
+        path: String,
+        /// JSON type of the value at `path`
+        found: &'static str,
+        #[source]
+        source: serde_json::Error,
+    },
+
+    #[error("signature verification failed: {0}")]
+    SignatureVerification(String),
+
+    #[error("invalid record: {0}")]
+    Validation(String),
+
+    /// `status` is not set when no response was received
+    #[error("intercom api request to {path} failed: {reason}")]
+    IntercomApi {
+        path: String,
+        status: Option<u16>,
+        reason: String,
+    },
+
+    #[error("failed to write to {sink}: {source}")]
+    SinkWrite {
+        sink: String,
+        #[source]
+        source: BoxError,
+    },
+
+    #[error("telemetry error: {0}")]
+    Telemetry(#[source] BoxError),
+}
+impl Error {
+    pub fn configuration(name: impl Into<String>, reason: impl ToString) -> Self {
+        Self::Configuration {
+            name: name.into(),
+            reason: reason.to_string(),
+        }
+    }
+
+    pub fn sink_write(sink: impl Into<String>, source: impl Into<BoxError>) -> Self {
+        Self::SinkWrite {
+            sink: sink.into(),
+            source: source.into(),
+        }
+    }
+
+    /// Whether the failure is transient, so the record may succeed if it is delivered again
+    ///
+    /// Permanent failures will fail the same way on every delivery
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::Configuration { .. } => false,
+            Self::Deserialization { .. } => false,
+            Self::SignatureVerification(_) => false,
+            Self::Validation(_) => false,
+            Self::IntercomApi { status, .. } => {
+                matches!(status, None | Some(429) | Some(500..))
+            }
+            Self::SinkWrite { .. } => true,
+            Self::Telemetry(_) => true,
+        }
+    }
+}
+
+/// Follow `path` through `value`, ignoring enum variants as they have no JSON counterpart
+fn lookup<'a>(value: &'a serde_json::Value, path: &Path) -> Option<&'a serde_json::Value> {
+    path.iter().try_fold(value, |value, segment| match segment {
+        Segment::Seq { index } => value.get(index),
+        Segment::Map { key } => value.get(key),
+        Segment::Enum { .. } => Some(value),
+        Segment::Unknown => None,
+    })
+}
+
+fn json_type(value: &serde_json::Value) -> &'static str {
+    match value {
+        serde_json::Value::Null => "null",
+        serde_json::Value::Bool(_) => "boolean",
+        serde_json::Value::Number(_) => "number",
+        serde_json::Value::String(_) => "string",
+        serde_json::Value::Array(_) => "array",
+        serde_json::Value::Object(_) => "object",
+    }
+}
+
+impl Error {
+    /// Build a deserialization error for `content`, locating the offending value
+    /// so its type can be reported alongside the path
+    pub fn deserialization(
+        content: &str,
+        e: serde_path_to_error::Error<serde_json::Error>,
+    ) -> Self {
+        let found = match serde_json::from_str::<serde_json::Value>(content) {
+            Ok(value) => lookup(&value, e.path()).map(json_type).unwrap_or("nothing"),
+            Err(_) => "invalid json",
+        };
+
+        Self::Deserialization {
+            path: e.path().to_string(),
+            found,
+            source: e.into_inner(),
+        }
+    }
+}
+
+/// Replace array indices so that a path can be used as a low cardinality metric attribute,
+/// `data.item.tags[3].applied_at` becomes `data.item.tags[].applied_at`
+pub fn path_pattern(path: &str) -> String {
+    let mut pattern = String::with_capacity(path.len());
+    let mut in_index = false;
+
+    for c in path.chars() {
+        match c {
+            '[' => {
+                in_index = true;
+                pattern.push(c);
+            }
+            ']' => {
+                in_index = false;
+                pattern.push(c);
+            }
+            _ if in_index => {}
+            _ => pattern.push(c),
+        }
+    }
+
+    pattern
+}
+
+impl From<opentelemetry::trace::TraceError> for Error {
+    fn from(e: opentelemetry::trace::TraceError) -> Self {
+        Self::Telemetry(e.into())
+    }
+}
+
+impl From<opentelemetry::metrics::MetricsError> for Error {
+    fn from(e: opentelemetry::metrics::MetricsError) -> Self {
+        Self::Telemetry(e.into())
+    }
+}
 
+/// Read a required environment variable
+pub fn env_var(name: &str) -> Result<String> {
+    std::env::var(name).map_err(|e| Error::configuration(name, e))
 }
@@ -0,0 +1,206 @@
+use std::time::Duration;
+
+use chrono::TimeZone;
+use lambda_runtime::tracing::{self, field::Empty, Span};
+use reqwest::{header::HeaderMap, Response, StatusCode};
+use tokio::sync::Mutex;
+
+use crate::{
+    domain::DateTime,
+    error::{Error, Result},
+};
+
+pub const DEFAULT_API_URL: &str = "https://api.intercom.io";
+const API_VERSION: &str = "2.11";
+
+/// Longest we wait for a rate limit to reset, beyond that the record is retried by SQS
+const MAX_RATE_LIMIT_WAIT: Duration = Duration::from_secs(10);
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The rate limit state Intercom reports with every response
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    pub remaining: u32,
+    pub reset_at: DateTime,
+}
+impl RateLimit {
+    /// Read `X-RateLimit-Remaining` and `X-RateLimit-Reset`, an epoch in seconds
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let header = |name| headers.get(name)?.to_str().ok()?.parse::<i64>().ok();
+
+        Some(Self {
+            remaining: header("x-ratelimit-remaining")?.try_into().ok()?,
+            reset_at: chrono::Utc
+                .timestamp_opt(header("x-ratelimit-reset")?, 0)
+                .single()?,
+        })
+    }
+
+    /// Time to wait before sending another request, `None` when requests are allowed
+    pub fn wait(&self, now: &DateTime) -> Option<Duration> {
+        if self.remaining > 0 {
+            return None;
+        }
+        (self.reset_at - *now)
+            .to_std()
+            .ok()
+            .filter(|wait| !wait.is_zero())
+    }
+}
+
+/// Client for the [Intercom REST API](https://developers.intercom.com/docs/references/rest-api/api.intercom.io/),
+/// shared by concurrent records so the rate limit is respected across them
+pub struct IntercomClient {
+    http: reqwest::Client,
+    api_url: String,
+    access_token: String,
+    max_retries: u32,
+    backoff: Duration,
+    rate_limit: Mutex<Option<RateLimit>>,
+}
+impl IntercomClient {
+    pub fn new(api_url: impl Into<String>, access_token: impl Into<String>) -> Result<Self> {
+        let http = reqwest::Client::builder()
+            .timeout(TIMEOUT)
+            .build()
+            .map_err(|e| Error::configuration("intercom client", e))?;
+
+        Ok(Self {
+            http,
+            api_url: api_url.into().trim_end_matches('/').into(),
+            access_token: access_token.into(),
+            max_retries: 3,
+            backoff: Duration::from_millis(200),
+            rate_limit: Mutex::new(None),
+        })
+    }
+
+    pub fn with_max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Delay before the first retry of a server error, doubled on every later retry
+    pub fn with_backoff(mut self, backoff: Duration) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    pub async fn conversation(&self, id: &str) -> Result<serde_json::Value> {
+        self.get(&format!("/conversations/{id}")).await
+    }
+
+    pub async fn contact(&self, id: &str) -> Result<serde_json::Value> {
+        self.get(&format!("/contacts/{id}")).await
+    }
+
+    pub async fn admins(&self) -> Result<serde_json::Value> {
+        self.get("/admins").await
+    }
+
+    pub async fn teams(&self) -> Result<serde_json::Value> {
+        self.get("/teams").await
+    }
+
+    /// Wait out an exhausted rate limit, failing when the reset is too far away
+    async fn wait_for_rate_limit(&self, path: &str) -> Result<()> {
+        let rate_limit = *self.rate_limit.lock().await;
+        let Some(wait) = rate_limit.and_then(|x| x.wait(&chrono::Utc::now())) else {
+            return Ok(());
+        };
+
+        if wait > MAX_RATE_LIMIT_WAIT {
+            return Err(Error::IntercomApi {
+                path: path.into(),
+                status: Some(StatusCode::TOO_MANY_REQUESTS.as_u16()),
+                reason: format!("rate limited for {}s", wait.as_secs()),
+            });
+        }
+
+        tokio::time::sleep(wait).await;
+        Ok(())
+    }
+
+    async fn send(&self, path: &str) -> Result<Response> {
+        self.wait_for_rate_limit(path).await?;
+
+        let response = self
+            .http
+            .get(format!("{}{path}", self.api_url))
+            .bearer_auth(&self.access_token)
+            .header("Accept", "application/json")
+            .header("Intercom-Version", API_VERSION)
+            .send()
+            .await
+            .map_err(|e| Error::IntercomApi {
+                path: path.into(),
+                status: e.status().map(|x| x.as_u16()),
+                reason: e.to_string(),
+            })?;
+
+        if let Some(rate_limit) = RateLimit::from_headers(response.headers()) {
+            *self.rate_limit.lock().await = Some(rate_limit);
+        }
+
+        Ok(response)
+    }
+
+    /// Server errors, rate limiting and dropped connections are retried up to `max_retries` times
+    #[tracing::instrument(
+        skip(self),
+        fields(http.request.method = "GET", http.response.status_code = Empty, attempts = Empty)
+    )]
+    async fn get(&self, path: &str) -> Result<serde_json::Value> {
+        let mut attempt = 0;
+
+        loop {
+            attempt += 1;
+            Span::current().record("attempts", attempt);
+
+            let error = match self.send(path).await {
+                Ok(response) if response.status().is_success() => {
+                    Span::current().record("http.response.status_code", response.status().as_u16());
+                    return response.json().await.map_err(|e| Error::IntercomApi {
+                        path: path.into(),
+                        status: None,
+                        reason: e.to_string(),
+                    });
+                }
+                Ok(response) => {
+                    let status = response.status();
+                    Span::current().record("http.response.status_code", status.as_u16());
+                    if status == StatusCode::TOO_MANY_REQUESTS {
+                        tracing::info!(monotonic_counter.intercom_rate_limited = 1_u64);
+                    }
+
+                    Error::IntercomApi {
+                        path: path.into(),
+                        status: Some(status.as_u16()),
+                        reason: response.text().await.unwrap_or_else(|e| e.to_string()),
+                    }
+                }
+                Err(e) => e,
+            };
+
+            if !error.is_retryable() || attempt > self.max_retries {
+                return Err(error);
+            }
+
+            tracing::warn!(
+                error = error.to_string(),
+                attempt,
+                "retrying intercom request"
+            );
+            // An exhausted rate limit is waited out before the next send instead
+            let now = chrono::Utc::now();
+            let rate_limited = self
+                .rate_limit
+                .lock()
+                .await
+                .is_some_and(|x| x.wait(&now).is_some());
+            if !rate_limited {
+                tokio::time::sleep(self.backoff * 2_u32.pow(attempt - 1)).await;
+            }
+        }
+    }
+}
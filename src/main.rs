@@ -1,64 +1,94 @@
 #[cfg(test)]
 mod tests;
 
+mod alerts;
+mod config;
+mod directory;
 mod domain;
+mod envelope;
 mod error;
+mod intercom;
+mod quarantine;
 mod telemetry;
 mod utils;
+mod workflow;
 
-use anyhow::{bail, Result};
-use aws_lambda_events::sqs::SqsEvent;
+use aws_lambda_events::sqs::{BatchItemFailure, SqsBatchResponse, SqsEvent, SqsMessage};
 use aws_sdk_s3::Client as S3Client;
-use domain::{conversation::Conversation, notification::Notification, DateTime};
-use lambda_runtime::{run, service_fn, tracing, Error, LambdaEvent};
-use telemetry::setup_telemetry;
+use aws_sdk_sqs::Client as SqsClient;
+use config::{Environment, PipelineConfig};
+use domain::{
+    admin::Admin,
+    company::Company,
+    contact::{Contact, ContactTag},
+    conversation::Conversation,
+    notification::Notification,
+    ticket::Ticket,
+    topic::{Object, Topic},
+    Item,
+};
+use error::{env_var, Error, Result};
+use lambda_runtime::{
+    run, service_fn,
+    tracing::{self, field::Empty, Span},
+    LambdaEvent,
+};
+use quarantine::Quarantine;
+use serde::{de::DeserializeOwned, Deserialize};
+use telemetry::{setup_telemetry, OtelGuard};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 use utils::Pipe;
-use uuid::Uuid;
+use workflow::{Pipeline, RecordContext};
 
-fn get_file_name(now: &DateTime, topic_name: &str, uuid: &Uuid) -> String {
-    let topic = topic_name.replace('.', "_");
-    let timestamp = now.format("%Y%m%d");
+fn deserialize<T: DeserializeOwned>(content: &str) -> Result<T> {
+    let de = &mut serde_json::Deserializer::from_str(content);
 
-    format!("{timestamp}_{topic}_{uuid}.json")
-}
-
-fn generate_file_name(topic_name: &str) -> String {
-    let now = chrono::Utc::now();
-    let uuid = Uuid::new_v4();
-    get_file_name(&now, topic_name, &uuid)
+    serde_path_to_error::deserialize(de).map_err(|e| {
+        let error = Error::deserialization(content, e);
+        if let Error::Deserialization { path, found, .. } = &error {
+            tracing::info!(
+                monotonic_counter.deserialization_errors = 1_u64,
+                json.path = error::path_pattern(path),
+                json.found = found,
+            );
+        }
+        error
+    })
 }
 
-async fn push_to_bucket(
-    client: &S3Client,
-    bucket_name: &str,
-    notification: &Notification<Conversation>,
-) -> Result<()> {
-    let key = generate_file_name(&notification.topic);
-    let content = serde_json::to_vec(notification)?;
-
-    client
-        .put_object()
-        .bucket(bucket_name)
-        .key(key)
-        .body(content.into())
-        .send()
-        .await?;
-
-    Ok(())
-}
+/// The item type is chosen by the object the topic is about, such as `ticket` in `ticket.created`,
+/// tag changes such as `contact.user.tag.created` carry the tag and the contact
+pub(crate) fn deserialize_notification(content: &str) -> Result<Notification<Item>> {
+    #[derive(Deserialize)]
+    struct Header {
+        topic: Topic,
+    }
 
-fn deserialize_conversation(content: &str) -> Option<Notification<Conversation>> {
-    let notification = serde_json::from_str::<Notification<Conversation>>(&content);
-    match notification {
-        Ok(notification) => Some(notification),
-        Err(e) => {
-            tracing::error!(error = e.to_string(), "record deserialization error");
-            None
+    let Header { topic } = deserialize(content)?;
+    match topic.object() {
+        Some(Object::Conversation) => deserialize::<Notification<Conversation>>(content)
+            .map(|x| x.map(|x| Item::Conversation(Box::new(x)))),
+        Some(Object::Ticket) => deserialize::<Notification<Ticket>>(content)
+            .map(|x| x.map(|x| Item::Ticket(Box::new(x)))),
+        Some(Object::Admin) => {
+            deserialize::<Notification<Admin>>(content).map(|x| x.map(|x| Item::Admin(Box::new(x))))
+        }
+        Some(Object::Company) => deserialize::<Notification<Company>>(content)
+            .map(|x| x.map(|x| Item::Company(Box::new(x)))),
+        Some(Object::Contact) if topic.action().is_some_and(|x| x.starts_with("tag.")) => {
+            deserialize::<Notification<ContactTag>>(content)
+                .map(|x| x.map(|x| Item::ContactTag(Box::new(x))))
         }
+        Some(Object::Contact) => deserialize::<Notification<Contact>>(content)
+            .map(|x| x.map(|x| Item::Contact(Box::new(x)))),
+        _ => Err(Error::Validation(format!(
+            "unsupported topic {:?}",
+            topic.to_string()
+        ))),
     }
 }
 
-fn log_errors<O, E>(msg: &str, results: &[Result<O, E>])
+pub(crate) fn log_errors<O, E>(msg: &str, results: &[Result<O, E>])
 where
     E: std::fmt::Display,
 {
@@ -69,39 +99,171 @@ where
     }
 }
 
-#[tracing::instrument]
-async fn function_handler(event: LambdaEvent<SqsEvent>) -> Result<()> {
-    let config = aws_config::load_defaults(aws_config::BehaviorVersion::v2024_03_28()).await;
+/// Span fields are recorded as the record is processed, the message body is never attached
+///
+/// The span continues the upstream trace when the message carries a trace context.
+/// Failures are logged inside the span, which marks it as an error so it is kept by sampling
+#[tracing::instrument(
+    skip_all,
+    err(Display),
+    fields(
+        messaging.system = "aws_sqs",
+        messaging.message.id = record.message_id.as_deref(),
+        notification.id = Empty,
+        notification.topic = Empty,
+        notification.delivery_attempts = Empty,
+        conversation.id = Empty,
+        ticket.id = Empty,
+        contact.id = Empty,
+        company.id = Empty,
+        admin.id = Empty,
+        s3.key = Empty,
+    )
+)]
+async fn process_record(pipeline: &Pipeline, record: &SqsMessage) -> Result<()> {
+    let span = Span::current();
+    if let Some(parent) = telemetry::extract_context(record) {
+        span.set_parent(parent);
+    }
 
-    let s3_client = aws_sdk_s3::Client::new(&config);
+    let mut cx = RecordContext::new(record);
+    let result = pipeline.run(&mut cx).await;
 
-    let bucket_name = std::env::var("OUTPUT_BUCKET")?;
+    if let Some(notification) = &cx.raw {
+        span.record("notification.id", &notification.id);
+        span.record("notification.topic", notification.topic.to_string());
+        span.record(
+            "notification.delivery_attempts",
+            notification.delivery_attempts,
+        );
+    }
+    if let Some(notification) = cx.parsed() {
+        match &notification.data {
+            Item::Conversation(conversation) => span.record("conversation.id", &conversation.id),
+            Item::Ticket(ticket) => span.record("ticket.id", &ticket.id),
+            Item::Contact(_) | Item::ContactTag(_) => {
+                span.record("contact.id", notification.data.id())
+            }
+            Item::Company(company) => span.record("company.id", &company.id),
+            Item::Admin(admin) => span.record("admin.id", &admin.id),
+        };
+    }
+    if !cx.written.is_empty() {
+        span.record("s3.key", cx.written.join(","));
+    }
 
-    let records: Vec<_> = event
-        .payload
-        .records
-        .into_iter()
-        .filter_map(|x| x.body)
-        .filter_map(|msg| deserialize_conversation(&msg))
-        .collect();
+    result
+}
 
-    let tasks = records
-        .iter()
-        .map(|r| push_to_bucket(&s3_client, &bucket_name, r));
+#[tracing::instrument(
+    skip_all,
+    fields(
+        faas.invocation_id = event.context.request_id,
+        record_count = event.payload.records.len(),
+    )
+)]
+async fn function_handler(
+    handler: &Handler<'_>,
+    event: LambdaEvent<SqsEvent>,
+) -> Result<SqsBatchResponse> {
+    let (pipeline, quarantine) = (&handler.pipeline, &handler.quarantine);
+    let tasks = event.payload.records.iter().map(|record| async move {
+        let result = match process_record(pipeline, record).await {
+            Ok(()) => Ok(()),
+            Err(e) => quarantine.handle_failure(record, e).await,
+        };
+        (record, result)
+    });
 
-    let task_results = futures::future::join_all(tasks).await;
-    log_errors("error pushing to bucket", &task_results);
+    let results = futures::future::join_all(tasks).await;
 
-    Ok(())
+    batch_response(&results)
+}
+
+/// Report retryable failures back to SQS so only those records are delivered again,
+/// records that failed permanently have been quarantined and are acknowledged
+///
+/// A retryable failure of a record without a message id cannot be reported, so the whole
+/// batch fails and is delivered again rather than losing the record
+pub(crate) fn batch_response(results: &[(&SqsMessage, Result<()>)]) -> Result<SqsBatchResponse> {
+    let mut batch_item_failures = vec![];
+    for (record, result) in results {
+        let Err(e) = result else { continue };
+        if !e.is_retryable() {
+            continue;
+        }
+
+        let Some(item_identifier) = record.message_id.clone() else {
+            tracing::error!(
+                error = e.to_string(),
+                "retryable failure of a record without a message id, failing the batch"
+            );
+            return Err(Error::Validation(
+                "retryable failure of a record without a message id".into(),
+            ));
+        };
+        batch_item_failures.push(BatchItemFailure { item_identifier });
+    }
+
+    Ok(SqsBatchResponse {
+        batch_item_failures,
+    })
+}
+
+/// State built once at cold start and shared by every invocation
+struct Handler<'a> {
+    pipeline: Pipeline,
+    quarantine: Quarantine<'a>,
+}
+impl<'a> Handler<'a> {
+    fn from_env(s3_client: &'a S3Client, sqs_client: &SqsClient) -> Result<Self> {
+        let pipeline = PipelineConfig::from_env()?.build(Environment {
+            s3_client,
+            sqs_client,
+            default_bucket: env_var("OUTPUT_BUCKET").ok(),
+            client_secret: env_var("INTERCOM_CLIENT_SECRET").ok(),
+            access_token: env_var("INTERCOM_ACCESS_TOKEN").ok(),
+        })?;
+        let quarantine = Quarantine::from_env(s3_client)?;
+
+        Ok(Self {
+            pipeline,
+            quarantine,
+        })
+    }
+}
+
+async fn handle_invocation(
+    handler: &Handler<'_>,
+    telemetry: Option<&OtelGuard>,
+    event: LambdaEvent<SqsEvent>,
+) -> Result<SqsBatchResponse> {
+    let result = function_handler(handler, event).await;
+
+    if let Some(telemetry) = telemetry {
+        telemetry.flush().await;
+    }
+
+    result
 }
 
 #[tokio::main]
-async fn main() -> Result<(), Error> {
-    let _telemetry = if std::env::var("DISABLE_TELEMETRY") != Ok("1".into()) {
+async fn main() -> Result<(), lambda_runtime::Error> {
+    let telemetry = if std::env::var("DISABLE_TELEMETRY") != Ok("1".into()) {
         setup_telemetry().await?.pipe(Some)
     } else {
         None
     };
 
-    run(service_fn(function_handler)).await
+    let aws_config = aws_config::load_defaults(aws_config::BehaviorVersion::v2024_03_28()).await;
+    let s3_client = aws_sdk_s3::Client::new(&aws_config);
+    let sqs_client = aws_sdk_sqs::Client::new(&aws_config);
+
+    let handler = Handler::from_env(&s3_client, &sqs_client)
+        .inspect_err(|e| tracing::error!(error = e.to_string(), "invalid configuration"))?;
+
+    run(service_fn(|event| {
+        handle_invocation(&handler, telemetry.as_ref(), event)
+    }))
+    .await
 }
@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+
+use aws_lambda_events::sqs::SqsMessage;
+use aws_sdk_s3::Client as S3Client;
+use lambda_runtime::tracing;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    domain::DateTime,
+    error::{env_var, Error, Result},
+};
+
+const DEFAULT_MAX_RECEIVE_COUNT: u32 = 5;
+const DEFAULT_PREFIX: &str = "quarantine/";
+
+/// A single failed attempt at processing a record
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct Failure {
+    pub failed_at: DateTime,
+    pub receive_count: u32,
+    /// Intercom's attempts at delivering the notification to the webhook, for context
+    #[serde(default)]
+    pub delivery_attempts: Option<u32>,
+    pub retryable: bool,
+    pub error: String,
+}
+
+/// Everything needed to inspect or replay a record that was taken out of the queue
+#[derive(Debug, Serialize)]
+pub struct QuarantinedRecord<'a> {
+    pub message_id: Option<&'a str>,
+    pub quarantined_at: DateTime,
+    pub receive_count: u32,
+    pub delivery_attempts: Option<u32>,
+    pub attributes: &'a HashMap<String, String>,
+    pub body: Option<&'a str>,
+    pub failures: Vec<Failure>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DeliveryAttempts {
+    delivery_attempts: u32,
+}
+
+/// Number of times SQS has delivered the record to the handler, its `ApproximateReceiveCount`
+pub fn receive_count(record: &SqsMessage) -> u32 {
+    record
+        .attributes
+        .get("ApproximateReceiveCount")
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(1)
+}
+
+/// Number of times Intercom tried to deliver the notification to the webhook endpoint,
+/// which says nothing about how often the handler has processed it
+pub fn delivery_attempts(record: &SqsMessage) -> Option<u32> {
+    record
+        .body
+        .as_deref()
+        .and_then(|body| serde_json::from_str::<DeliveryAttempts>(body).ok())
+        .map(|x| x.delivery_attempts)
+}
+
+/// Permanent failures are quarantined immediately, retryable ones once they reach the threshold
+pub fn should_quarantine(error: &Error, receive_count: u32, max_receive_count: u32) -> bool {
+    !error.is_retryable() || receive_count >= max_receive_count
+}
+
+pub(crate) struct Quarantine<'a> {
+    client: &'a S3Client,
+    bucket_name: String,
+    prefix: String,
+    max_receive_count: u32,
+}
+impl<'a> Quarantine<'a> {
+    pub fn new(
+        client: &'a S3Client,
+        bucket_name: String,
+        prefix: String,
+        max_receive_count: u32,
+    ) -> Self {
+        Self {
+            client,
+            bucket_name,
+            prefix,
+            max_receive_count,
+        }
+    }
+
+    /// Configured by `QUARANTINE_BUCKET` (defaults to `OUTPUT_BUCKET`),
+    /// `QUARANTINE_PREFIX` and `MAX_RECEIVE_COUNT`
+    pub fn from_env(client: &'a S3Client) -> Result<Self> {
+        let bucket_name = env_var("QUARANTINE_BUCKET").or_else(|_| env_var("OUTPUT_BUCKET"))?;
+        let prefix = env_var("QUARANTINE_PREFIX").unwrap_or_else(|_| DEFAULT_PREFIX.into());
+        let max_receive_count = match env_var("MAX_RECEIVE_COUNT") {
+            Ok(count) => count
+                .parse()
+                .map_err(|e| Error::configuration("MAX_RECEIVE_COUNT", e))?,
+            Err(_) => DEFAULT_MAX_RECEIVE_COUNT,
+        };
+
+        Ok(Self::new(client, bucket_name, prefix, max_receive_count))
+    }
+
+    fn record_id(record: &SqsMessage) -> String {
+        record
+            .message_id
+            .clone()
+            .unwrap_or_else(|| Uuid::new_v4().to_string())
+    }
+
+    fn history_key(&self, record_id: &str) -> String {
+        format!("{}failures/{record_id}.json", self.prefix)
+    }
+
+    fn quarantine_key(&self, record_id: &str) -> String {
+        format!("{}records/{record_id}.json", self.prefix)
+    }
+
+    async fn read_history(&self, key: &str) -> Result<Vec<Failure>> {
+        let response = self
+            .client
+            .get_object()
+            .bucket(&self.bucket_name)
+            .key(key)
+            .send()
+            .await;
+
+        let object = match response {
+            Ok(object) => object,
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_no_such_key()) => {
+                return Ok(vec![])
+            }
+            Err(e) => return Err(Error::sink_write(&self.bucket_name, e)),
+        };
+
+        let content = object
+            .body
+            .collect()
+            .await
+            .map_err(|e| Error::sink_write(&self.bucket_name, e))?
+            .into_bytes();
+
+        serde_json::from_slice(&content)
+            .map_err(|e| Error::Validation(format!("unreadable failure history: {e}")))
+    }
+
+    async fn write(&self, key: &str, content: &impl Serialize) -> Result<()> {
+        let content = serde_json::to_vec(content)
+            .map_err(|e| Error::Validation(format!("unserializable {key}: {e}")))?;
+
+        self.client
+            .put_object()
+            .bucket(&self.bucket_name)
+            .key(key)
+            .body(content.into())
+            .send()
+            .await
+            .map_err(|e| Error::sink_write(&self.bucket_name, e))?;
+
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        self.client
+            .delete_object()
+            .bucket(&self.bucket_name)
+            .key(key)
+            .send()
+            .await
+            .map_err(|e| Error::sink_write(&self.bucket_name, e))?;
+
+        Ok(())
+    }
+
+    /// Record the failure and decide what happens to the record
+    ///
+    /// Returns `Ok` when the record was quarantined and can be acknowledged,
+    /// otherwise the error to report back to SQS so the record is retried
+    pub async fn handle_failure(&self, record: &SqsMessage, error: Error) -> Result<()> {
+        let record_id = Self::record_id(record);
+        let receive_count = receive_count(record);
+        let delivery_attempts = delivery_attempts(record);
+        let history_key = self.history_key(&record_id);
+
+        let mut failures = self.read_history(&history_key).await.unwrap_or_else(|e| {
+            tracing::warn!(error = e.to_string(), "error reading failure history");
+            vec![]
+        });
+        failures.push(Failure {
+            failed_at: chrono::Utc::now(),
+            receive_count,
+            delivery_attempts,
+            retryable: error.is_retryable(),
+            error: error.to_string(),
+        });
+
+        if !should_quarantine(&error, receive_count, self.max_receive_count) {
+            if let Err(e) = self.write(&history_key, &failures).await {
+                tracing::warn!(error = e.to_string(), "error writing failure history");
+            }
+            return Err(error);
+        }
+
+        let quarantined = QuarantinedRecord {
+            message_id: record.message_id.as_deref(),
+            quarantined_at: chrono::Utc::now(),
+            receive_count,
+            delivery_attempts,
+            attributes: &record.attributes,
+            body: record.body.as_deref(),
+            failures,
+        };
+        self.write(&self.quarantine_key(&record_id), &quarantined)
+            .await?;
+
+        tracing::warn!(
+            messaging.message.id = record.message_id.as_deref(),
+            receive_count,
+            "record quarantined"
+        );
+        tracing::info!(monotonic_counter.quarantined_records = 1_u64);
+
+        if let Err(e) = self.delete(&history_key).await {
+            tracing::warn!(error = e.to_string(), "error deleting failure history");
+        }
+
+        Ok(())
+    }
+}
@@ -1,49 +1,118 @@
 use std::env;
 use std::time::Duration;
 
-use anyhow::Result;
-
+use aws_lambda_events::sqs::SqsMessage;
 use lambda_runtime::tracing::{
     self,
     metadata::LevelFilter,
     subscriber::{layer::SubscriberExt, util::SubscriberInitExt},
     Level,
 };
-use opentelemetry::KeyValue;
+use opentelemetry::{
+    propagation::{Extractor, TextMapPropagator},
+    trace::{
+        Link, SamplingDecision, SamplingResult, SpanContext, SpanKind, Status, TraceContextExt,
+        TraceId, TraceResult, TracerProvider as _,
+    },
+    Context, KeyValue,
+};
+use opentelemetry_aws::trace::XrayPropagator;
 use opentelemetry_otlp::{TonicExporterBuilder, WithExportConfig};
 use opentelemetry_sdk::{
+    export::trace::SpanData,
     metrics::{
         reader::{DefaultAggregationSelector, DefaultTemporalitySelector},
         Instrument, MeterProviderBuilder, PeriodicReader, SdkMeterProvider, Stream,
     },
+    propagation::TraceContextPropagator,
+    resource::{EnvResourceDetector, ResourceDetector},
     runtime,
-    trace::Tracer,
+    trace::{
+        BatchSpanProcessor, Sampler, ShouldSample, Span, SpanProcessor, Tracer, TracerProvider,
+    },
     Resource,
 };
+use opentelemetry_semantic_conventions::resource as semconv;
 use tracing_opentelemetry::{MetricsLayer, OpenTelemetryLayer};
 
-use crate::utils::Pipe;
+use crate::{
+    error::{env_var, Error, Result},
+    log_errors,
+    utils::Pipe,
+};
 
-fn setup_exporter() -> TonicExporterBuilder {
-    let otel_exporter_endpoint = std::env::var("OTEL_ENDPOINT").expect("OTEL_ENDPOINT not set");
+fn setup_exporter() -> Result<TonicExporterBuilder> {
+    let otel_exporter_endpoint = env_var("OTEL_ENDPOINT")?;
 
-    opentelemetry_otlp::new_exporter()
+    let exporter = opentelemetry_otlp::new_exporter()
         .tonic()
-        .with_endpoint(otel_exporter_endpoint)
+        .with_endpoint(otel_exporter_endpoint);
+
+    Ok(exporter)
+}
+
+/// Detects FaaS attributes from the variables set by the Lambda runtime
+/// https://docs.aws.amazon.com/lambda/latest/dg/configuration-envvars.html#configuration-envvars-runtime
+struct LambdaResourceDetector;
+impl ResourceDetector for LambdaResourceDetector {
+    fn detect(&self, _timeout: Duration) -> Resource {
+        lambda_attributes(|key| env::var(key).ok()).pipe(Resource::new)
+    }
 }
 
+pub(crate) fn lambda_attributes(var: impl Fn(&str) -> Option<String>) -> Vec<KeyValue> {
+    let Some(function_name) = var("AWS_LAMBDA_FUNCTION_NAME") else {
+        return vec![];
+    };
+
+    let mut attributes = vec![
+        KeyValue::new(semconv::CLOUD_PROVIDER, "aws"),
+        KeyValue::new(semconv::CLOUD_PLATFORM, "aws_lambda"),
+        KeyValue::new(semconv::FAAS_NAME, function_name),
+    ];
+
+    if let Some(version) = var("AWS_LAMBDA_FUNCTION_VERSION") {
+        attributes.push(KeyValue::new(semconv::FAAS_VERSION, version));
+    }
+    if let Some(instance) = var("AWS_LAMBDA_LOG_STREAM_NAME") {
+        attributes.push(KeyValue::new(semconv::FAAS_INSTANCE, instance));
+    }
+    if let Some(region) = var("AWS_REGION") {
+        attributes.push(KeyValue::new(semconv::CLOUD_REGION, region));
+    }
+    // Lambda reports memory in MiB, the convention expects bytes
+    if let Some(memory) = var("AWS_LAMBDA_FUNCTION_MEMORY_SIZE").and_then(|x| x.parse::<i64>().ok())
+    {
+        attributes.push(KeyValue::new(
+            semconv::FAAS_MAX_MEMORY,
+            memory * 1024 * 1024,
+        ));
+    }
+
+    attributes
+}
+
+/// Attributes are merged in order of increasing precedence:
+/// service defaults, then the Lambda environment, then `OTEL_RESOURCE_ATTRIBUTES`
 fn resource() -> Result<Resource> {
-    Ok(Resource::new([
-        KeyValue::new("SERVICE_NAME", env!("CARGO_PKG_NAME")),
-        KeyValue::new("SERVICE_VERSION", env!("CARGO_PKG_VERSION")),
-        KeyValue::new("DEPLOYMENT_ENVIRONMENT", env::var("ENVIRONMENT")?),
-    ]))
+    let service = Resource::new([
+        KeyValue::new(semconv::SERVICE_NAME, env!("CARGO_PKG_NAME")),
+        KeyValue::new(semconv::SERVICE_VERSION, env!("CARGO_PKG_VERSION")),
+        KeyValue::new(semconv::DEPLOYMENT_ENVIRONMENT, env_var("ENVIRONMENT")?),
+    ]);
+
+    let timeout = Duration::from_secs(0);
+    let resource = service
+        .merge(&LambdaResourceDetector.detect(timeout))
+        .merge(&EnvResourceDetector::new().detect(timeout));
+
+    Ok(resource)
 }
 
 fn setup_meter_views(meter_provider_builder: MeterProviderBuilder) -> SdkMeterProvider {
     let view_payload_recieved = |instrument: &Instrument| -> Option<Stream> {
         //TODO: Add implementation here
-        if instrument.name != "" {
+        if !instrument.name.is_empty() {
             return None;
         }
 
@@ -56,7 +125,7 @@ fn setup_meter_views(meter_provider_builder: MeterProviderBuilder) -> SdkMeterPr
 }
 
 fn setup_meter_provider() -> Result<SdkMeterProvider> {
-    let exporter = setup_exporter().build_metrics_exporter(
+    let exporter = setup_exporter()?.build_metrics_exporter(
         Box::new(DefaultAggregationSelector::default()),
         Box::new(DefaultTemporalitySelector::default()),
     )?;
@@ -75,18 +144,199 @@ fn setup_meter_provider() -> Result<SdkMeterProvider> {
     Ok(meter_provider)
 }
 
-fn setup_tracer() -> Result<Tracer> {
-    let tracer = opentelemetry_otlp::new_pipeline()
-        .tracing()
-        .with_exporter(setup_exporter())
-        .with_batch_config(opentelemetry_sdk::trace::BatchConfig::default())
-        .install_batch(opentelemetry_sdk::runtime::Tokio)?;
+/// Build a sampler from `OTEL_TRACES_SAMPLER` and `OTEL_TRACES_SAMPLER_ARG`
+///
+/// Follows the [SDK environment variable specification](https://opentelemetry.io/docs/specs/otel/configuration/sdk-environment-variables/#general-sdk-configuration),
+/// defaulting to `parentbased_always_on`
+pub(crate) fn sampler(name: Option<&str>, arg: Option<&str>) -> Result<Sampler> {
+    let ratio = || -> Result<f64> {
+        match arg {
+            None => Ok(1.0),
+            Some(arg) => match arg.parse::<f64>() {
+                Ok(ratio) if (0.0..=1.0).contains(&ratio) => Ok(ratio),
+                _ => Err(Error::configuration(
+                    "OTEL_TRACES_SAMPLER_ARG",
+                    format!("expected a ratio between 0 and 1, got {arg:?}"),
+                )),
+            },
+        }
+    };
+
+    let sampler = match name.unwrap_or("parentbased_always_on") {
+        "always_on" => Sampler::AlwaysOn,
+        "always_off" => Sampler::AlwaysOff,
+        "traceidratio" => Sampler::TraceIdRatioBased(ratio()?),
+        "parentbased_always_on" => Sampler::ParentBased(Box::new(Sampler::AlwaysOn)),
+        "parentbased_always_off" => Sampler::ParentBased(Box::new(Sampler::AlwaysOff)),
+        "parentbased_traceidratio" => {
+            Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(ratio()?)))
+        }
+        other => {
+            return Err(Error::configuration(
+                "OTEL_TRACES_SAMPLER",
+                format!("unsupported sampler {other:?}"),
+            ))
+        }
+    };
+
+    Ok(sampler)
+}
+
+/// Records the spans the inner sampler drops instead of discarding them,
+/// so [`ErrorSpanProcessor`] can still export the ones that fail
+#[derive(Debug, Clone)]
+pub(crate) struct RecordDropped(pub Sampler);
+impl ShouldSample for RecordDropped {
+    fn should_sample(
+        &self,
+        parent_context: Option<&Context>,
+        trace_id: TraceId,
+        name: &str,
+        span_kind: &SpanKind,
+        attributes: &[KeyValue],
+        links: &[Link],
+    ) -> SamplingResult {
+        let mut result =
+            self.0
+                .should_sample(parent_context, trace_id, name, span_kind, attributes, links);
+
+        if result.decision == SamplingDecision::Drop {
+            result.decision = SamplingDecision::RecordOnly;
+        }
+
+        result
+    }
+}
+
+/// Tail rule that marks unsampled spans ending with an error status as sampled
+/// before handing them to the inner processor, so failures are always exported
+#[derive(Debug)]
+pub(crate) struct ErrorSpanProcessor<P>(pub P);
+impl<P: SpanProcessor> SpanProcessor for ErrorSpanProcessor<P> {
+    fn on_start(&self, span: &mut Span, cx: &Context) {
+        self.0.on_start(span, cx)
+    }
+
+    fn on_end(&self, mut span: SpanData) {
+        if !span.span_context.is_sampled() && matches!(span.status, Status::Error { .. }) {
+            let cx = &span.span_context;
+            span.span_context = SpanContext::new(
+                cx.trace_id(),
+                cx.span_id(),
+                cx.trace_flags().with_sampled(true),
+                cx.is_remote(),
+                cx.trace_state().clone(),
+            );
+        }
+
+        self.0.on_end(span)
+    }
+
+    fn force_flush(&self) -> TraceResult<()> {
+        self.0.force_flush()
+    }
+
+    fn shutdown(&mut self) -> TraceResult<()> {
+        self.0.shutdown()
+    }
+}
+
+fn setup_tracer_provider() -> Result<TracerProvider> {
+    let exporter = setup_exporter()?.build_span_exporter()?;
+    let processor = BatchSpanProcessor::builder(exporter, runtime::Tokio).build();
+
+    let sampler = sampler(
+        env::var("OTEL_TRACES_SAMPLER").ok().as_deref(),
+        env::var("OTEL_TRACES_SAMPLER_ARG").ok().as_deref(),
+    )?;
 
-    Ok(tracer)
+    let tracer_provider = TracerProvider::builder()
+        .with_span_processor(ErrorSpanProcessor(processor))
+        .with_config(
+            opentelemetry_sdk::trace::config()
+                .with_resource(resource()?)
+                .with_sampler(RecordDropped(sampler)),
+        )
+        .build();
+
+    opentelemetry::global::set_tracer_provider(tracer_provider.clone());
+
+    Ok(tracer_provider)
 }
 
+/// Reads propagation headers from the message attributes of an SQS record
+///
+/// The X-Ray header is also read from the `AWSTraceHeader` system attribute,
+/// which SQS sets when the sender has active tracing
+struct SqsExtractor<'a>(&'a SqsMessage);
+impl Extractor for SqsExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        let attribute = self
+            .0
+            .message_attributes
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(key))
+            .and_then(|(_, attribute)| attribute.string_value.as_deref());
+
+        match attribute {
+            Some(value) => Some(value),
+            None if key.eq_ignore_ascii_case("x-amzn-trace-id") => {
+                self.0.attributes.get("AWSTraceHeader").map(String::as_str)
+            }
+            None => None,
+        }
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0
+            .message_attributes
+            .keys()
+            .map(String::as_str)
+            .collect()
+    }
+}
+
+/// Extract the upstream trace context of a record, preferring W3C `traceparent` over X-Ray
+pub(crate) fn extract_context(record: &SqsMessage) -> Option<Context> {
+    let extractor = SqsExtractor(record);
+
+    let is_valid = |cx: &Context| cx.span().span_context().is_valid();
+
+    Some(TraceContextPropagator::new().extract(&extractor))
+        .filter(is_valid)
+        .or_else(|| Some(XrayPropagator::new().extract(&extractor)).filter(is_valid))
+}
+
+/// Upper bound on the time an invocation spends exporting telemetry
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
 pub(crate) struct OtelGuard {
     meter_provider: SdkMeterProvider,
+    tracer_provider: TracerProvider,
+}
+impl OtelGuard {
+    /// Export everything recorded so far
+    ///
+    /// Lambda freezes the container between invocations, so the batch exporter and
+    /// periodic reader cannot be relied on to run before data is lost
+    pub(crate) async fn flush(&self) {
+        let meter_provider = self.meter_provider.clone();
+        let tracer_provider = self.tracer_provider.clone();
+
+        // force_flush blocks until the exporters respond
+        let flush = tokio::task::spawn_blocking(move || {
+            log_errors("error flushing traces", &tracer_provider.force_flush());
+
+            log_errors("error flushing metrics", &[meter_provider.force_flush()]);
+        });
+
+        if tokio::time::timeout(FLUSH_TIMEOUT, flush).await.is_err() {
+            tracing::warn!(
+                timeout_ms = FLUSH_TIMEOUT.as_millis(),
+                "telemetry flush timed out"
+            );
+        }
+    }
 }
 impl Drop for OtelGuard {
     fn drop(&mut self) {
@@ -99,6 +349,8 @@ impl Drop for OtelGuard {
 
 pub(crate) async fn setup_telemetry() -> Result<OtelGuard> {
     let meter_provider = setup_meter_provider()?;
+    let tracer_provider = setup_tracer_provider()?;
+    let tracer: Tracer = tracer_provider.tracer(env!("CARGO_PKG_NAME"));
 
     let json_layer = tracing::subscriber::fmt::layer().json();
 
@@ -106,8 +358,11 @@ pub(crate) async fn setup_telemetry() -> Result<OtelGuard> {
         .with(LevelFilter::from_level(Level::INFO))
         .with(json_layer)
         .with(MetricsLayer::new(meter_provider.clone()))
-        .with(OpenTelemetryLayer::new(setup_tracer()?))
+        .with(OpenTelemetryLayer::new(tracer))
         .init();
 
-    Ok(OtelGuard { meter_provider })
+    Ok(OtelGuard {
+        meter_provider,
+        tracer_provider,
+    })
 }
@@ -0,0 +1,157 @@
+use super::{dt, notification, s};
+use std::sync::Arc;
+
+use aws_lambda_events::sqs::SqsMessage;
+
+use crate::{
+    config::{OutputFormat, PipelineConfig},
+    deserialize_notification,
+    domain::{admin::*, Item},
+    workflow::{
+        AcceptTopics, Decode, Parse, Pipeline, RecordContext, RouteAvailability, RouteByTopic,
+    },
+};
+
+const ADMIN_JSON: &str = include_str!("./data_files/admin.json");
+
+fn admin_json() -> serde_json::Value {
+    serde_json::from_str(ADMIN_JSON).unwrap()
+}
+
+#[test]
+fn admin_deserialization_test() {
+    let admin: Admin = serde_json::from_str(ADMIN_JSON).expect("Failed to deserialize admin");
+
+    assert_eq!(
+        admin,
+        Admin {
+            typ: s("admin"),
+            id: s("991267"),
+            name: Some(s("Operator")),
+            email: Some(s("operator+abcd1234@intercom.io")),
+            job_title: Some(s("Support")),
+            away_mode_enabled: true,
+            away_mode_reassign: true,
+            has_inbox_seat: Some(true),
+            team_ids: vec![5017691],
+        }
+    )
+}
+
+#[test]
+fn notifications_are_parsed_by_topic() {
+    for topic in [
+        "admin.added_to_workspace",
+        "admin.away_mode_updated",
+        "admin.logged_in",
+        "admin.removed_from_workspace",
+    ] {
+        let parsed = deserialize_notification(&notification(topic, admin_json())).unwrap();
+        assert!(matches!(parsed.data, Item::Admin(_)), "{topic}");
+        assert_eq!("991267", parsed.data.id());
+    }
+
+    // Events other than away mode changes only carry the admin id
+    let parsed = deserialize_notification(&notification(
+        "admin.logged_in",
+        serde_json::json!({ "type": "admin", "id": "991267" }),
+    ))
+    .unwrap();
+    let Item::Admin(admin) = parsed.data else {
+        panic!("expected admin");
+    };
+    assert!(!admin.away_mode_enabled);
+}
+
+async fn route(config: &str, topic: &str) -> RecordContext {
+    let config = PipelineConfig::parse(config)
+        .unwrap()
+        .with_default_bucket(Some("output"))
+        .unwrap();
+    let routes = Arc::new(config.routes);
+    let availability = config.availability.unwrap();
+    let pipeline = Pipeline::new()
+        .with_stage(Decode)
+        .with_stage(AcceptTopics::new(routes.clone()))
+        .with_stage(Parse)
+        .with_stage(RouteByTopic::new(routes))
+        .with_stage(RouteAvailability::new(
+            availability.bucket.unwrap(),
+            availability.key_template,
+            availability.format,
+        ));
+
+    let mut cx = RecordContext::new(&SqsMessage {
+        body: Some(notification(topic, admin_json())),
+        ..Default::default()
+    });
+    pipeline.run(&mut cx).await.unwrap();
+    cx
+}
+
+#[tokio::test]
+async fn away_mode_changes_are_streamed_as_availability() {
+    let cx = route(
+        include_str!("../../pipeline.toml"),
+        "admin.away_mode_updated",
+    )
+    .await;
+
+    assert_eq!(2, cx.destinations.len());
+    assert_eq!(None, cx.destinations[0].document);
+    assert!(cx.destinations[0].key.contains("_admin_away_mode_updated_"));
+
+    let availability = &cx.destinations[1];
+    assert_eq!("output", availability.bucket);
+    assert!(availability.key.starts_with("availability/"));
+    assert!(availability.key.contains("/991267_"));
+    assert_eq!(
+        Availability {
+            admin_id: s("991267"),
+            away: true,
+            reassign: true,
+            changed_at: dt("2014-02-18T13:48:51Z"),
+            notification_id: s("notif_1"),
+        },
+        serde_json::from_value(availability.document.clone().unwrap()).unwrap()
+    );
+}
+
+#[tokio::test]
+async fn other_admin_events_are_only_archived() {
+    let cx = route(include_str!("../../pipeline.toml"), "admin.logged_in").await;
+
+    assert_eq!(1, cx.destinations.len());
+    assert_eq!(None, cx.destinations[0].document);
+}
+
+#[test]
+fn availability_config() {
+    let routes = "[[routes]]\ntopics = [\"*\"]\nsinks = [{ type = \"s3\", bucket = \"output\" }]\n";
+
+    assert!(PipelineConfig::parse(routes)
+        .unwrap()
+        .availability
+        .is_none());
+
+    let config = PipelineConfig::parse(&format!(
+        "[availability]\nbucket = \"facts\"\nformat = \"ndjson\"\n{routes}"
+    ))
+    .unwrap();
+    let availability = config
+        .with_default_bucket(None)
+        .unwrap()
+        .availability
+        .unwrap();
+    assert_eq!(Some(s("facts")), availability.bucket);
+    assert_eq!(OutputFormat::Ndjson, availability.format);
+
+    let config = PipelineConfig::parse(&format!("[availability]\n{routes}")).unwrap();
+    assert!(config.with_default_bucket(None).is_err());
+
+    let error = PipelineConfig::parse(&format!(
+        "[availability]\nkey_template = \"{{item_id}}.json\"\n{routes}"
+    ))
+    .unwrap_err();
+    assert!(error.starts_with("availability: "), "{error}");
+}
@@ -0,0 +1,432 @@
+use std::time::Duration;
+
+use aws_lambda_events::sqs::SqsMessage;
+use chrono::TimeZone;
+use wiremock::{
+    matchers::{body_json, method, path},
+    Mock, MockServer, ResponseTemplate,
+};
+
+use crate::{
+    alerts::{within_window, Alert, AlertDedup, AlertRule, AlertSink, MarkerBucket, WebhookFormat},
+    config::{Conditions, PipelineConfig},
+    deserialize_notification,
+    domain::{conversation::SLAStatus, timestamp::TimestampFormat},
+    workflow::{Alerts, Decode, Parse, Pipeline, RecordContext},
+};
+
+use super::{conversation_json, conversation_notification, s, s3_client};
+
+/// The fixture conversation with a missed SLA and a rating of 1
+fn unhappy_conversation() -> serde_json::Value {
+    let mut conversation = conversation_json();
+    conversation["sla_applied"]["sla_name"] = "First reply".into();
+    conversation["sla_applied"]["sla_status"] = "missed".into();
+    conversation["conversation_rating"]["rating"] = 1.into();
+    conversation
+}
+
+fn now() -> chrono::DateTime<chrono::Utc> {
+    chrono::Utc.with_ymd_and_hms(2024, 1, 2, 10, 30, 0).unwrap()
+}
+
+#[test]
+fn alert_conditions() {
+    let happy = deserialize_notification(&conversation_notification(conversation_json())).unwrap();
+    let unhappy =
+        deserialize_notification(&conversation_notification(unhappy_conversation())).unwrap();
+
+    let sla_missed = Conditions {
+        sla_status: Some(vec![SLAStatus::Missed]),
+        ..Default::default()
+    };
+    assert!(sla_missed.matches(&unhappy));
+    assert!(!sla_missed.matches(&happy));
+
+    let negative_rating = Conditions {
+        max_rating: Some(2),
+        ..Default::default()
+    };
+    assert!(negative_rating.matches(&unhappy));
+    assert!(!negative_rating.matches(&happy));
+
+    let mut unrated = conversation_json();
+    unrated["conversation_rating"] = serde_json::Value::Null;
+    unrated["sla_applied"] = serde_json::Value::Null;
+    let unrated = deserialize_notification(&conversation_notification(unrated)).unwrap();
+    assert!(!negative_rating.matches(&unrated));
+    assert!(!sla_missed.matches(&unrated));
+}
+
+#[test]
+fn alert_summary() {
+    let notification =
+        deserialize_notification(&conversation_notification(unhappy_conversation())).unwrap();
+    let conversation = notification.data.as_conversation().unwrap();
+    let alert = Alert::new("unhappy", &notification, conversation, now());
+
+    assert_eq!("1295", alert.conversation_id);
+    assert_eq!(Some(SLAStatus::Missed), alert.sla_status);
+    assert_eq!(Some(1), alert.rating);
+    assert_eq!(
+        "unhappy: conversation 1295, SLA First reply missed, rated 1",
+        alert.summary()
+    );
+
+    for status in [
+        SLAStatus::Hit,
+        SLAStatus::Missed,
+        SLAStatus::Cancelled,
+        SLAStatus::Active,
+    ] {
+        assert_eq!(
+            serde_json::json!(status.to_string()),
+            serde_json::json!(status)
+        );
+    }
+}
+
+#[test]
+fn dedup_window() {
+    let window = Duration::from_secs(60);
+    let recent = now() - chrono::Duration::seconds(59);
+    let old = now() - chrono::Duration::seconds(60);
+
+    assert!(within_window(Some(&recent), &now(), window));
+    assert!(!within_window(Some(&old), &now(), window));
+    assert!(!within_window(None, &now(), window));
+}
+
+#[tokio::test]
+async fn webhook_sinks() {
+    let server = MockServer::start().await;
+    let notification =
+        deserialize_notification(&conversation_notification(unhappy_conversation())).unwrap();
+    let conversation = notification.data.as_conversation().unwrap();
+    let alert = Alert::new("unhappy", &notification, conversation, now());
+
+    Mock::given(method("POST"))
+        .and(path("/slack"))
+        .and(body_json(serde_json::json!({ "text": alert.summary() })))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/json"))
+        .and(body_json(&alert))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/failing"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&server)
+        .await;
+
+    let webhook = |route: &str, format| AlertSink::Webhook {
+        client: reqwest::Client::new(),
+        url: format!("{}/{route}", server.uri()),
+        format,
+    };
+
+    webhook("slack", WebhookFormat::Slack)
+        .send(&alert, TimestampFormat::default())
+        .await
+        .unwrap();
+    webhook("json", WebhookFormat::Json)
+        .send(&alert, TimestampFormat::default())
+        .await
+        .unwrap();
+    assert!(webhook("failing", WebhookFormat::Json)
+        .send(&alert, TimestampFormat::default())
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn file_sink_appends_lines() {
+    let path = std::env::temp_dir().join(format!("alerts_{}.ndjson", uuid::Uuid::new_v4()));
+    let notification =
+        deserialize_notification(&conversation_notification(unhappy_conversation())).unwrap();
+    let conversation = notification.data.as_conversation().unwrap();
+    let alert = Alert::new("unhappy", &notification, conversation, now());
+    let sink = AlertSink::File { path: path.clone() };
+
+    sink.send(&alert, TimestampFormat::default()).await.unwrap();
+    sink.send(&alert, TimestampFormat::default()).await.unwrap();
+
+    let content = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(path).unwrap();
+    let lines: Vec<Alert> = content
+        .lines()
+        .map(|x| serde_json::from_str(x).unwrap())
+        .collect();
+    assert_eq!(vec![alert.clone(), alert], lines);
+}
+
+#[tokio::test]
+async fn alerts_are_written_in_the_timestamp_format() {
+    let path = std::env::temp_dir().join(format!("alerts_{}.ndjson", uuid::Uuid::new_v4()));
+    let notification =
+        deserialize_notification(&conversation_notification(unhappy_conversation())).unwrap();
+    let conversation = notification.data.as_conversation().unwrap();
+    let alert = Alert::new("unhappy", &notification, conversation, now());
+    let sink = AlertSink::File { path: path.clone() };
+
+    sink.send(&alert, TimestampFormat::EpochSeconds)
+        .await
+        .unwrap();
+    sink.send(&alert, TimestampFormat::Rfc3339).await.unwrap();
+
+    let content = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(path).unwrap();
+    let lines: Vec<serde_json::Value> = content
+        .lines()
+        .map(|x| serde_json::from_str(x).unwrap())
+        .collect();
+    assert_eq!(serde_json::json!(1704191400), lines[0]["triggered_at"]);
+    assert_eq!(
+        serde_json::json!("2024-01-02T10:30:00Z"),
+        lines[1]["triggered_at"]
+    );
+}
+
+#[tokio::test]
+async fn conversations_alert_once_per_rule() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&server)
+        .await;
+
+    let rule = |name: &str, when| AlertRule {
+        name: name.into(),
+        when,
+        sinks: vec![AlertSink::Webhook {
+            client: reqwest::Client::new(),
+            url: server.uri(),
+            format: WebhookFormat::Slack,
+        }],
+        dedup_window: Duration::from_secs(3600),
+    };
+    let rules = vec![
+        rule(
+            "sla_missed",
+            Conditions {
+                sla_status: Some(vec![SLAStatus::Missed]),
+                ..Default::default()
+            },
+        ),
+        rule(
+            "negative_rating",
+            Conditions {
+                max_rating: Some(2),
+                ..Default::default()
+            },
+        ),
+        rule(
+            "vip",
+            Conditions {
+                tags: Some(vec![s("vip")]),
+                ..Default::default()
+            },
+        ),
+    ];
+    let pipeline = Pipeline::new()
+        .with_stage(Decode)
+        .with_stage(Parse)
+        .with_stage(Alerts::new(rules, AlertDedup::new(None)));
+
+    for _ in 0..3 {
+        let mut cx = RecordContext::new(&SqsMessage {
+            body: Some(conversation_notification(unhappy_conversation())),
+            ..Default::default()
+        });
+        pipeline.run(&mut cx).await.unwrap();
+    }
+}
+
+#[test]
+fn alert_rules_are_configured() {
+    let config = PipelineConfig::parse(
+        r#"
+[[alerts]]
+name = "unhappy"
+when = { max_rating = 2 }
+sinks = [
+    { type = "sqs", queue_url = "https://sqs.eu-west-1.amazonaws.com/1/alerts" },
+    { type = "webhook", url = "https://hooks.slack.com/x", format = "slack" },
+    { type = "file", path = "/tmp/alerts.ndjson" },
+]
+
+[[routes]]
+topics = ["*"]
+"#,
+    )
+    .unwrap();
+
+    assert_eq!(1, config.alerts.len());
+    assert_eq!(Some(2), config.alerts[0].when.max_rating);
+    assert_eq!(3, config.alerts[0].sinks.len());
+    assert_eq!(24 * 60 * 60, config.alerts[0].dedup_secs);
+
+    let cases = [
+        (
+            "[[alerts]]\nname = \"a\"\nwhen = {}\nsinks = [{ type = \"file\", path = \"a\" }]",
+            "alerts[0]: at least one condition",
+        ),
+        (
+            "[[alerts]]\nname = \"a\"\nwhen = { max_rating = 2 }\nsinks = []",
+            "alerts[0]: at least one sink",
+        ),
+        (
+            "[[alerts]]\nname = \"a\"\nwhen = { max_rating = 2 }\nsinks = [{ type = \"email\" }]",
+            "unknown variant `email`",
+        ),
+    ];
+    for (alerts, expected) in cases {
+        let error =
+            PipelineConfig::parse(&format!("{alerts}\n[[routes]]\ntopics = [\"*\"]")).unwrap_err();
+        assert!(
+            error.contains(expected),
+            "expected {expected:?} in error for {alerts:?}, got {error:?}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn concurrent_records_alert_once() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(50)))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let rules = vec![AlertRule {
+        name: s("negative_rating"),
+        when: Conditions {
+            max_rating: Some(2),
+            ..Default::default()
+        },
+        sinks: vec![AlertSink::Webhook {
+            client: reqwest::Client::new(),
+            url: server.uri(),
+            format: WebhookFormat::Slack,
+        }],
+        dedup_window: Duration::from_secs(3600),
+    }];
+    let pipeline = Pipeline::new()
+        .with_stage(Decode)
+        .with_stage(Parse)
+        .with_stage(Alerts::new(rules, AlertDedup::new(None)));
+
+    // A rating and a close of the same conversation in one batch
+    let mut rated = RecordContext::new(&SqsMessage {
+        body: Some(conversation_notification(unhappy_conversation())),
+        ..Default::default()
+    });
+    let mut closed = rated.clone();
+    let (a, b) = futures::join!(pipeline.run(&mut rated), pipeline.run(&mut closed));
+    a.unwrap();
+    b.unwrap();
+}
+
+#[tokio::test]
+async fn failed_alerts_are_retried_with_the_record() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let rules = vec![AlertRule {
+        name: s("negative_rating"),
+        when: Conditions {
+            max_rating: Some(2),
+            ..Default::default()
+        },
+        sinks: vec![AlertSink::Webhook {
+            client: reqwest::Client::new(),
+            url: server.uri(),
+            format: WebhookFormat::Slack,
+        }],
+        dedup_window: Duration::from_secs(3600),
+    }];
+    let pipeline = Pipeline::new()
+        .with_stage(Decode)
+        .with_stage(Parse)
+        .with_stage(Alerts::new(rules, AlertDedup::new(None)));
+    let record = SqsMessage {
+        body: Some(conversation_notification(unhappy_conversation())),
+        ..Default::default()
+    };
+
+    let error = pipeline
+        .run(&mut RecordContext::new(&record))
+        .await
+        .unwrap_err();
+    assert!(error.is_retryable());
+
+    // The redelivery sends the alert, and later ones are duplicates
+    pipeline
+        .run(&mut RecordContext::new(&record))
+        .await
+        .unwrap();
+    pipeline
+        .run(&mut RecordContext::new(&record))
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn unsent_alerts_are_released() {
+    let dedup = AlertDedup::new(None);
+    let window = Duration::from_secs(3600);
+
+    assert!(!dedup
+        .is_duplicate("rule", "1295", &now(), window)
+        .await
+        .unwrap());
+    assert!(dedup
+        .is_duplicate("rule", "1295", &now(), window)
+        .await
+        .unwrap());
+
+    dedup.release("rule", "1295", &now()).await;
+    assert!(!dedup
+        .is_duplicate("rule", "1295", &now(), window)
+        .await
+        .unwrap());
+}
+
+#[tokio::test]
+async fn unreadable_markers_are_permanent_failures() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("not json"))
+        .mount(&server)
+        .await;
+
+    let dedup = AlertDedup::new(Some(MarkerBucket {
+        client: s3_client(&server.uri()),
+        bucket_name: s("output"),
+        prefix: s("alerts/"),
+    }));
+    let error = dedup
+        .is_duplicate("rule", "1295", &now(), Duration::from_secs(3600))
+        .await
+        .unwrap_err();
+
+    assert!(!error.is_retryable(), "{error}");
+}
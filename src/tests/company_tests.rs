@@ -0,0 +1,99 @@
+use super::{dt, notification, s};
+use std::sync::Arc;
+
+use aws_lambda_events::sqs::SqsMessage;
+
+use crate::{
+    config::PipelineConfig,
+    deserialize_notification,
+    domain::{company::*, contact::TagReference, Item},
+    workflow::{AcceptTopics, Decode, Parse, Pipeline, RecordContext, RouteByTopic},
+};
+
+const COMPANY_JSON: &str = include_str!("./data_files/company.json");
+
+fn company_json() -> serde_json::Value {
+    serde_json::from_str(COMPANY_JSON).unwrap()
+}
+
+#[test]
+fn company_deserialization_test() {
+    let company: Company =
+        serde_json::from_str(COMPANY_JSON).expect("Failed to deserialize company");
+
+    assert_eq!(
+        company,
+        Company {
+            typ: s("company"),
+            id: s("6762f0dd1bb69f9f2193bb83"),
+            name: s("Blue Sun"),
+            app_id: Some(s("this_is_an_id_123_a")),
+            company_id: s("remote_companies_scroll_2"),
+            plan: Some(Plan {
+                typ: s("plan"),
+                id: s("269315"),
+                name: s("Pro"),
+            }),
+            monthly_spend: 9001,
+            user_count: 12,
+            session_count: 164,
+            size: Some(100),
+            website: Some(s("https://www.example.com")),
+            industry: Some(s("Manufacturing")),
+            remote_created_at: Some(dt("2024-06-27T12:54:40Z")),
+            created_at: Some(dt("2024-06-27T12:54:40Z")),
+            updated_at: Some(dt("2024-06-27T12:57:45Z")),
+            last_request_at: Some(dt("2024-06-27T12:57:45Z")),
+            custom_attributes: [
+                (s("creation_source"), serde_json::json!("api")),
+                (s("seats"), serde_json::json!(40)),
+            ]
+            .into(),
+            segments: vec![Segment {
+                typ: s("segment"),
+                id: s("5310d8e7598c9a0b24000002"),
+                name: s("Active"),
+            }],
+            tags: vec![TagReference {
+                typ: s("tag"),
+                id: s("123456"),
+                name: s("VIP"),
+            }],
+        }
+    )
+}
+
+#[test]
+fn notifications_are_parsed_by_topic() {
+    for topic in ["company.created", "company.updated"] {
+        let parsed = deserialize_notification(&notification(topic, company_json())).unwrap();
+        assert!(matches!(parsed.data, Item::Company(_)), "{topic}");
+        assert_eq!("6762f0dd1bb69f9f2193bb83", parsed.data.id());
+    }
+}
+
+#[tokio::test]
+async fn companies_are_archived() {
+    let config = PipelineConfig::parse(include_str!("../../pipeline.toml")).unwrap();
+    let config = config.with_default_bucket(Some("output")).unwrap();
+    let routes = Arc::new(config.routes);
+    let pipeline = Pipeline::new()
+        .with_stage(Decode)
+        .with_stage(AcceptTopics::new(routes.clone()))
+        .with_stage(Parse)
+        .with_stage(RouteByTopic::new(routes));
+
+    let mut cx = RecordContext::new(&SqsMessage {
+        body: Some(notification("company.updated", company_json())),
+        ..Default::default()
+    });
+    pipeline.run(&mut cx).await.unwrap();
+
+    assert_eq!(1, cx.destinations.len());
+    assert_eq!("output", cx.destinations[0].bucket);
+    assert!(cx.destinations[0].key.contains("_company_updated_"));
+
+    let document = cx.document().unwrap();
+    assert_eq!("Pro", document["data"]["plan"]["name"]);
+    assert_eq!(9001, document["data"]["monthly_spend"]);
+}
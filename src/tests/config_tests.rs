@@ -0,0 +1,520 @@
+use std::sync::Arc;
+
+use aws_lambda_events::sqs::SqsMessage;
+
+use crate::{
+    config::{
+        Conditions, DirectoryConfig, DirectorySourceConfig, Enrichment, OutputFormat,
+        PipelineConfig, SinkConfig,
+    },
+    deserialize_notification,
+    domain::conversation::{ConversationState, SLAStatus, SourceType},
+    error::Error,
+    utils::glob_match,
+    workflow::{
+        redact, AcceptTopics, AcceptWorkspaces, ApplyFilters, Decode, ErrorPolicy, Parse, Pipeline,
+        RecordContext, Redact, RouteByTopic,
+    },
+};
+
+use super::{conversation_json, conversation_notification, s};
+
+const CONFIG: &str = r#"
+[defaults]
+format = "ndjson"
+redact = ["data.custom_attributes"]
+sinks = [{ type = "s3", bucket = "archive" }]
+
+[[routes]]
+topics = ["conversation.admin.*", "conversation.user.created"]
+key_template = "conversations/{topic_path}/{item_id}_{uuid}.json"
+redact = ["data.contacts.*.external_id", "data.tags"]
+sinks = [{ type = "s3" }, { type = "s3", bucket = "analytics" }]
+
+[[routes]]
+topics = ["conversation.*"]
+"#;
+
+#[test]
+fn bundled_config_is_valid() {
+    let config = PipelineConfig::parse(include_str!("../../pipeline.toml")).unwrap();
+
+    assert_eq!(1, config.routes.len());
+    assert!(config.routes[0].matches(&"contact.user.created".into(), "a86dr8yl"));
+    assert!(config.routes[0].redact.is_empty());
+}
+
+#[test]
+fn routes_fall_back_to_defaults() {
+    let config = PipelineConfig::parse(CONFIG).unwrap();
+
+    let admin = &config.routes[0];
+    assert_eq!(OutputFormat::Ndjson, admin.format);
+    assert_eq!(
+        vec![s("data.contacts.*.external_id"), s("data.tags")],
+        admin.redact
+    );
+    assert_eq!(
+        vec![
+            SinkConfig::S3 { bucket: None },
+            SinkConfig::S3 {
+                bucket: Some(s("analytics"))
+            }
+        ],
+        admin.sinks
+    );
+
+    let other = &config.routes[1];
+    assert_eq!(vec![s("data.custom_attributes")], other.redact);
+    assert_eq!(
+        vec![SinkConfig::S3 {
+            bucket: Some(s("archive"))
+        }],
+        other.sinks
+    );
+}
+
+#[test]
+fn api_settings_fall_back_to_defaults() {
+    let config = PipelineConfig::parse(
+        "[enrichment]\n[directory]\nsource = { type = \"file\", path = \"directory.json\" }\n[[routes]]\ntopics = [\"*\"]",
+    )
+    .unwrap();
+
+    assert_eq!(
+        Some(Enrichment {
+            topics: vec![s("conversation.*")],
+            contacts: false,
+            on_error: ErrorPolicy::Fail,
+            api_url: s("https://api.intercom.io"),
+            max_retries: 3,
+            backoff_ms: 200,
+        }),
+        config.enrichment
+    );
+    assert_eq!(
+        Some(DirectoryConfig {
+            source: DirectorySourceConfig::File {
+                path: "directory.json".into()
+            },
+            ttl_secs: 3600,
+            on_error: ErrorPolicy::Skip,
+        }),
+        config.directory
+    );
+}
+
+#[test]
+fn default_bucket_required_by_sinks_without_one() {
+    let config = PipelineConfig::parse("[[routes]]\ntopics = [\"*\"]").unwrap();
+    assert!(config.with_default_bucket(None).is_err());
+
+    let config = PipelineConfig::parse(CONFIG).unwrap();
+    assert!(config.with_default_bucket(None).is_err());
+}
+
+#[test]
+fn invalid_configs_are_rejected() {
+    let cases = [
+        ("routes = []", "at least one route"),
+        ("[[routes]]\ntopics = []", "routes[0]: topics"),
+        (
+            "[[routes]]\ntopics = [\"*\"]\nformat = \"xml\"",
+            "unknown variant `xml`",
+        ),
+        (
+            "[[routes]]\ntopics = [\"*\"]\ncolour = \"blue\"",
+            "unknown field `colour`",
+        ),
+        (
+            "[[routes]]\ntopics = [\"*\"]\nkey_template = \"{date}/{id}_{uuid}\"",
+            "routes[0]: unknown placeholder {id}",
+        ),
+        (
+            "[[routes]]\ntopics = [\"*\"]\nkey_template = \"{date}_{topic}.json\"",
+            "must contain {uuid}",
+        ),
+        (
+            "[[routes]]\ntopics = [\"*\"]\nkey_template = \"{date_{uuid}\"",
+            "unknown placeholder",
+        ),
+        (
+            "[[routes]]\ntopics = [\"*\"]\nredact = [\"data..tags\"]",
+            "invalid redact path",
+        ),
+        (
+            "[[routes]]\ntopics = [\"*\"]\nsinks = []",
+            "at least one sink",
+        ),
+        (
+            "[[routes]]\ntopics = [\"*\"]\nsinks = [{ type = \"gcs\" }]",
+            "unknown variant `gcs`",
+        ),
+        (
+            "[[routes]]\nname = \"a\"\ntopics = [\"*\"]\n[[routes]]\nname = \"a\"\ntopics = [\"*\"]",
+            "route name \"a\" is used more than once",
+        ),
+        (
+            "[[filters]]\nname = \"f\"\nwhen = {}\naction = { type = \"drop\" }\n[[routes]]\ntopics = [\"*\"]",
+            "filters[0]: at least one condition",
+        ),
+        (
+            "[[filters]]\nname = \"f\"\nwhen = { state = [\"open\"] }\naction = { type = \"route\", route = \"vip\" }\n[[routes]]\ntopics = [\"*\"]",
+            "filters[0]: unknown route \"vip\"",
+        ),
+        (
+            "[[filters]]\nname = \"f\"\nwhen = { state = [\"pending\"] }\naction = { type = \"drop\" }\n[[routes]]\ntopics = [\"*\"]",
+            "unknown variant `pending`",
+        ),
+    ];
+
+    for (config, expected) in cases {
+        let error = PipelineConfig::parse(config).unwrap_err();
+        assert!(
+            error.contains(expected),
+            "expected {expected:?} in error for {config:?}, got {error:?}"
+        );
+    }
+}
+
+#[test]
+fn topic_globs() {
+    assert!(glob_match("*", "conversation.admin.closed"));
+    assert!(glob_match("conversation.*", "conversation.admin.closed"));
+    assert!(glob_match(
+        "conversation.*.closed",
+        "conversation.admin.closed"
+    ));
+    assert!(glob_match(
+        "conversation.admin.closed",
+        "conversation.admin.closed"
+    ));
+    assert!(!glob_match("conversation.*", "contact.user.created"));
+    assert!(!glob_match("*.closed", "conversation.admin.closed.extra"));
+}
+
+#[test]
+fn redact_paths() {
+    let mut value = serde_json::json!({
+        "id": "1",
+        "contacts": [
+            { "id": "a", "external_id": "x" },
+            { "id": "b", "external_id": "y" },
+        ],
+        "custom_attributes": { "plan": "pro", "email": "a@b.c" },
+    });
+
+    redact(&mut value, &["contacts", "*", "external_id"]);
+    redact(&mut value, &["custom_attributes", "*"]);
+    redact(&mut value, &["missing", "field"]);
+
+    assert_eq!(
+        serde_json::json!({
+            "id": "1",
+            "contacts": [{ "id": "a" }, { "id": "b" }],
+            "custom_attributes": {},
+        }),
+        value
+    );
+}
+
+#[test]
+fn output_formats() {
+    let document = serde_json::json!({ "id": "1" });
+
+    assert_eq!(
+        b"{\"id\":\"1\"}".to_vec(),
+        OutputFormat::Json.encode(&document).unwrap()
+    );
+    assert_eq!(
+        b"{\"id\":\"1\"}\n".to_vec(),
+        OutputFormat::Ndjson.encode(&document).unwrap()
+    );
+    assert_eq!(
+        b"{\n  \"id\": \"1\"\n}".to_vec(),
+        OutputFormat::PrettyJson.encode(&document).unwrap()
+    );
+}
+
+fn configured_pipeline(config: &str) -> Pipeline {
+    let config = PipelineConfig::parse(config)
+        .unwrap()
+        .with_default_bucket(Some("output"))
+        .unwrap();
+    let routes = Arc::new(config.routes);
+    Pipeline::new()
+        .with_stage(Decode)
+        .with_stage(AcceptTopics::new(routes.clone()).with_filters(&config.filters))
+        .with_stage(Parse)
+        .with_stage(AcceptWorkspaces::new(config.workspaces.map(|x| x.allow)))
+        .with_stage(ApplyFilters::new(config.filters))
+        .with_stage(Redact::new(routes.clone()))
+        .with_stage(RouteByTopic::new(routes))
+}
+
+#[tokio::test]
+async fn records_are_routed_and_redacted() {
+    let pipeline = configured_pipeline(CONFIG);
+    let mut cx = RecordContext::new(&SqsMessage {
+        body: Some(conversation_notification(conversation_json())),
+        ..Default::default()
+    });
+
+    pipeline.run(&mut cx).await.unwrap();
+
+    let buckets: Vec<_> = cx.destinations.iter().map(|x| x.bucket.as_str()).collect();
+    assert_eq!(vec!["output", "analytics"], buckets);
+    assert!(cx.destinations[0]
+        .key
+        .starts_with("conversations/conversation/admin/closed/1295_"));
+    assert_eq!(OutputFormat::Ndjson, cx.destinations[0].format);
+
+    let output = cx.output.unwrap();
+    assert!(output["data"].get("tags").is_none());
+    assert!(output["data"]["contacts"][0].get("external_id").is_none());
+    assert_eq!(
+        "5ba682d23d7cf92bef87bfd4",
+        output["data"]["contacts"][0]["id"]
+    );
+    assert!(output["data"].get("custom_attributes").is_some());
+}
+
+#[tokio::test]
+async fn redeliveries_write_the_same_keys() {
+    let pipeline = configured_pipeline(CONFIG);
+    let route = |message_id: &str| {
+        let mut cx = RecordContext::new(&SqsMessage {
+            message_id: Some(s(message_id)),
+            body: Some(conversation_notification(conversation_json())),
+            ..Default::default()
+        });
+        let pipeline = &pipeline;
+        async move {
+            pipeline.run(&mut cx).await.unwrap();
+            cx.destinations
+                .into_iter()
+                .map(|x| x.key)
+                .collect::<Vec<_>>()
+        }
+    };
+
+    let keys = route("m1").await;
+    assert_eq!(keys, route("m1").await);
+    assert_ne!(keys, route("m2").await);
+}
+
+#[tokio::test]
+async fn unmatched_topics_are_not_routed() {
+    let pipeline = configured_pipeline(
+        "[[routes]]\ntopics = [\"contact.*\"]\nsinks = [{ type = \"s3\", bucket = \"b\" }]",
+    );
+    let mut cx = RecordContext::new(&SqsMessage {
+        body: Some(conversation_notification(conversation_json())),
+        ..Default::default()
+    });
+
+    pipeline.run(&mut cx).await.unwrap();
+
+    assert!(cx.destinations.is_empty());
+    assert!(cx.output.is_none());
+}
+
+#[tokio::test]
+async fn unmodelled_topics_without_a_route_are_acknowledged() {
+    let pipeline = configured_pipeline(
+        "[[routes]]\ntopics = [\"conversation.*\"]\nsinks = [{ type = \"s3\", bucket = \"b\" }]",
+    );
+
+    for topic in [
+        "visitor.signed_up",
+        "conversation_part.tag.created",
+        "event.created",
+    ] {
+        let body = serde_json::json!({
+            "type": "notification_event",
+            "app_id": "a86dr8yl",
+            "id": "notif_1",
+            "topic": topic,
+            "data": { "item": { "type": "visitor" } },
+        });
+        let mut cx = RecordContext::new(&SqsMessage {
+            body: Some(body.to_string()),
+            ..Default::default()
+        });
+
+        assert!(pipeline.run(&mut cx).await.is_ok(), "{topic}");
+        assert!(cx.destinations.is_empty(), "{topic}");
+        assert!(cx.raw.is_none(), "{topic}");
+    }
+}
+
+#[test]
+fn filter_conditions() {
+    let notification =
+        deserialize_notification(&conversation_notification(conversation_json())).unwrap();
+    let matches = |conditions: Conditions| conditions.matches(&notification);
+
+    assert!(matches(Conditions {
+        topics: Some(vec![s("conversation.admin.*")]),
+        app_id: Some(vec![s("a86dr8yl")]),
+        team_assignee_id: Some(vec![s("1"), s("5017691")]),
+        tags: Some(vec![s("Test tag")]),
+        state: Some(vec![ConversationState::Open]),
+        ai_agent_source_type: Some(vec![SourceType::Workflow]),
+        custom_attributes: Some([(s("property1"), s("str*"))].into()),
+        sla_status: Some(vec![SLAStatus::Hit]),
+        max_rating: Some(5),
+    }));
+
+    assert!(!matches(Conditions {
+        topics: Some(vec![s("contact.*")]),
+        ..Default::default()
+    }));
+    assert!(!matches(Conditions {
+        app_id: Some(vec![s("other_workspace")]),
+        ..Default::default()
+    }));
+    assert!(!matches(Conditions {
+        team_assignee_id: Some(vec![s("1")]),
+        ..Default::default()
+    }));
+    assert!(!matches(Conditions {
+        tags: Some(vec![s("vip")]),
+        ..Default::default()
+    }));
+    assert!(!matches(Conditions {
+        state: Some(vec![ConversationState::Closed, ConversationState::Snoozed]),
+        ..Default::default()
+    }));
+    assert!(!matches(Conditions {
+        ai_agent_source_type: Some(vec![SourceType::WorkflowPreview]),
+        ..Default::default()
+    }));
+    assert!(!matches(Conditions {
+        custom_attributes: Some([(s("property1"), s("other"))].into()),
+        ..Default::default()
+    }));
+    assert!(!matches(Conditions {
+        custom_attributes: Some([(s("missing"), s("*"))].into()),
+        ..Default::default()
+    }));
+}
+
+const FILTER_CONFIG: &str = r#"
+[defaults]
+sinks = [{ type = "s3", bucket = "archive" }]
+
+[[filters]]
+name = "support"
+when = { team_assignee_id = ["5017691"] }
+action = { type = "tag", label = "support" }
+
+[[filters]]
+name = "tagged"
+when = { tags = ["Test tag"] }
+action = { type = "route", route = "tagged" }
+
+[[routes]]
+topics = ["conversation.*"]
+
+[[routes]]
+name = "tagged"
+topics = ["tagged.*"]
+key_template = "tagged/{uuid}.json"
+"#;
+
+#[tokio::test]
+async fn filters_route_and_tag_records() {
+    let pipeline = configured_pipeline(FILTER_CONFIG);
+    let mut cx = RecordContext::new(&SqsMessage {
+        body: Some(conversation_notification(conversation_json())),
+        ..Default::default()
+    });
+
+    pipeline.run(&mut cx).await.unwrap();
+
+    assert_eq!(Some(s("tagged")), cx.route);
+    assert_eq!(1, cx.destinations.len());
+    assert!(cx.destinations[0].key.starts_with("tagged/"));
+    assert_eq!(serde_json::json!(["support"]), cx.output.unwrap()["labels"]);
+}
+
+#[tokio::test]
+async fn filters_drop_records() {
+    let config = format!(
+        "[[filters]]\nname = \"workflows\"\nwhen = {{ ai_agent_source_type = [\"workflow\"] }}\naction = {{ type = \"drop\" }}\n{FILTER_CONFIG}"
+    );
+    let pipeline = configured_pipeline(&config);
+    let mut cx = RecordContext::new(&SqsMessage {
+        body: Some(conversation_notification(conversation_json())),
+        ..Default::default()
+    });
+
+    pipeline.run(&mut cx).await.unwrap();
+
+    assert!(cx.destinations.is_empty());
+    assert!(cx.route.is_none());
+    assert!(cx.output.is_none());
+}
+
+const WORKSPACE_CONFIG: &str = r#"
+[workspaces]
+allow = ["a86dr8yl", "eu_workspace"]
+
+[[routes]]
+topics = ["*"]
+app_ids = ["eu_workspace"]
+key_template = "{app_id}/{date}_{topic}_{uuid}.json"
+sinks = [{ type = "s3", bucket = "eu-archive" }]
+
+[[routes]]
+topics = ["*"]
+key_template = "{app_id}/{date}_{topic}_{uuid}.json"
+"#;
+
+fn workspace_record(app_id: &str) -> RecordContext {
+    let mut body: serde_json::Value =
+        serde_json::from_str(&conversation_notification(conversation_json())).unwrap();
+    body["app_id"] = app_id.into();
+
+    RecordContext::new(&SqsMessage {
+        body: Some(body.to_string()),
+        ..Default::default()
+    })
+}
+
+#[tokio::test]
+async fn workspaces_are_routed_by_app_id() {
+    let pipeline = configured_pipeline(WORKSPACE_CONFIG);
+
+    for (app_id, bucket) in [("eu_workspace", "eu-archive"), ("a86dr8yl", "output")] {
+        let mut cx = workspace_record(app_id);
+        pipeline.run(&mut cx).await.unwrap();
+
+        assert_eq!(1, cx.destinations.len(), "{app_id}");
+        assert_eq!(bucket, cx.destinations[0].bucket);
+        assert!(cx.destinations[0].key.starts_with(&format!("{app_id}/")));
+        assert_eq!(app_id, cx.document().unwrap()["app_id"]);
+    }
+}
+
+#[tokio::test]
+async fn unknown_workspaces_are_rejected() {
+    let pipeline = configured_pipeline(WORKSPACE_CONFIG);
+    let mut cx = workspace_record("unknown_workspace");
+
+    match pipeline.run(&mut cx).await {
+        Err(e @ Error::Validation(_)) => assert!(!e.is_retryable()),
+        other => panic!("expected validation error, got {other:?}"),
+    }
+    assert!(cx.destinations.is_empty());
+}
+
+#[test]
+fn workspace_config_is_validated() {
+    let routes = "[[routes]]\ntopics = [\"*\"]\n";
+
+    assert!(PipelineConfig::parse(routes).unwrap().workspaces.is_none());
+    assert!(PipelineConfig::parse(&format!("[workspaces]\nallow = []\n{routes}")).is_err());
+    assert!(PipelineConfig::parse(&format!("{routes}app_ids = []\n")).is_err());
+}
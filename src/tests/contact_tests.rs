@@ -0,0 +1,225 @@
+use super::{dt, notification, s};
+
+use aws_lambda_events::sqs::SqsMessage;
+
+use crate::{
+    config::PipelineConfig,
+    deserialize_notification,
+    domain::{contact::*, Item},
+    workflow::{Decode, Parse, PiiHandling, Pipeline, ProtectPii, RecordContext},
+};
+
+const CONTACT_JSON: &str = include_str!("./data_files/contact.json");
+const CONTACT_TAG_JSON: &str = include_str!("./data_files/contact_tag.json");
+
+fn contact_json() -> serde_json::Value {
+    serde_json::from_str(CONTACT_JSON).unwrap()
+}
+
+fn contact_tag_json() -> serde_json::Value {
+    serde_json::from_str(CONTACT_TAG_JSON).unwrap()
+}
+
+#[test]
+fn contact_deserialization_test() {
+    let contact: Contact =
+        serde_json::from_str(CONTACT_JSON).expect("Failed to deserialize contact");
+
+    assert_eq!(
+        contact,
+        Contact {
+            typ: s("contact"),
+            id: s("5ba682d23d7cf92bef87bfd4"),
+            workspace_id: Some(s("this_is_an_id_123_a")),
+            external_id: Some(s("f3b87a2e09d514c6c2e79b9a")),
+            role: Some(Role::User),
+            email: Some(s("joe@example.com")),
+            email_domain: Some(s("example.com")),
+            phone: Some(s("+1123456789")),
+            formatted_phone: Some(s("+1 123-456-789")),
+            name: Some(s("Joe Bloggs")),
+            owner_id: Some(991267),
+            has_hard_bounced: false,
+            marked_email_as_spam: false,
+            unsubscribed_from_emails: true,
+            created_at: Some(dt("2024-06-27T12:54:40Z")),
+            updated_at: Some(dt("2024-06-27T12:57:45Z")),
+            signed_up_at: Some(dt("2024-06-27T12:54:40Z")),
+            last_seen_at: Some(dt("2024-06-27T12:57:45Z")),
+            last_replied_at: None,
+            last_contacted_at: Some(dt("2024-06-27T12:55:40Z")),
+            last_email_opened_at: None,
+            last_email_clicked_at: None,
+            language_override: None,
+            browser: Some(s("chrome")),
+            browser_version: Some(s("126.0.0.0")),
+            browser_language: Some(s("en")),
+            os: Some(s("OS X 10.15.7")),
+            location: Some(Location {
+                typ: s("location"),
+                country: Some(s("Ireland")),
+                region: Some(s("Dublin")),
+                city: Some(s("Dublin")),
+                country_code: Some(s("IRL")),
+                continent_code: Some(s("EU")),
+            }),
+            avatar: Some(Avatar {
+                typ: s("avatar"),
+                image_url: Some(s("https://example.org/128Wash.jpg")),
+            }),
+            custom_attributes: [
+                (s("plan"), serde_json::json!("pro")),
+                (s("seats"), serde_json::json!(12)),
+                (s("beta"), serde_json::json!(true)),
+            ]
+            .into(),
+            tags: vec![AddressableReference {
+                typ: s("tag"),
+                id: s("123456"),
+                url: Some(s("/tags/123456")),
+            }],
+            companies: vec![AddressableReference {
+                typ: s("company"),
+                id: s("6762f0dd1bb69f9f2193bb83"),
+                url: Some(s("/companies/6762f0dd1bb69f9f2193bb83")),
+            }],
+            social_profiles: vec![SocialProfile {
+                typ: s("social_profile"),
+                name: s("Twitter"),
+                url: s("http://twitter.com/joebloggs"),
+            }],
+        }
+    )
+}
+
+#[test]
+fn contact_roles() {
+    let roles: Vec<Role> = serde_json::from_str(r#"["user", "lead"]"#).unwrap();
+
+    assert_eq!(vec![Role::User, Role::Lead], roles);
+}
+
+#[test]
+fn deleted_contacts_only_need_an_id() {
+    let contact: Contact = serde_json::from_value(serde_json::json!({
+        "type": "contact",
+        "id": "5ba682d23d7cf92bef87bfd4",
+    }))
+    .unwrap();
+
+    assert_eq!(None, contact.role);
+    assert_eq!(None, contact.created_at);
+    assert!(contact.tags.is_empty());
+}
+
+#[test]
+fn notifications_are_parsed_by_topic() {
+    for topic in [
+        "contact.user.created",
+        "contact.lead.created",
+        "contact.user.updated",
+        "contact.lead.signed_up",
+    ] {
+        let parsed = deserialize_notification(&notification(topic, contact_json())).unwrap();
+        assert!(matches!(parsed.data, Item::Contact(_)), "{topic}");
+        assert_eq!("5ba682d23d7cf92bef87bfd4", parsed.data.id());
+    }
+
+    for topic in ["contact.user.tag.created", "contact.lead.tag.deleted"] {
+        let parsed = deserialize_notification(&notification(topic, contact_tag_json())).unwrap();
+        let Item::ContactTag(contact_tag) = &parsed.data else {
+            panic!("expected contact tag for {topic}");
+        };
+        assert_eq!("VIP", contact_tag.tag.name);
+        assert_eq!(Some(Role::User), contact_tag.contact.role);
+        assert_eq!("5ba682d23d7cf92bef87bfd4", parsed.data.id());
+    }
+}
+
+async fn protect(handling: PiiHandling, topic: &str, item: serde_json::Value) -> serde_json::Value {
+    let fields = Contact::PII_FIELDS.iter().map(|x| x.to_string()).collect();
+    let pipeline = Pipeline::new()
+        .with_stage(Decode)
+        .with_stage(Parse)
+        .with_stage(ProtectPii::new(handling, fields));
+
+    let mut cx = RecordContext::new(&SqsMessage {
+        body: Some(notification(topic, item)),
+        ..Default::default()
+    });
+    pipeline.run(&mut cx).await.unwrap();
+
+    cx.document().unwrap()
+}
+
+#[tokio::test]
+async fn pii_is_kept() {
+    let document = protect(PiiHandling::Keep, "contact.user.created", contact_json()).await;
+
+    assert_eq!("joe@example.com", document["data"]["email"]);
+    assert_eq!("Dublin", document["data"]["location"]["city"]);
+}
+
+#[tokio::test]
+async fn pii_is_redacted() {
+    let document = protect(PiiHandling::Redact, "contact.user.created", contact_json()).await;
+    let data = &document["data"];
+
+    for field in Contact::PII_FIELDS {
+        assert!(data.get(field).is_none(), "{field}");
+    }
+    assert_eq!("5ba682d23d7cf92bef87bfd4", data["id"]);
+    assert_eq!("pro", data["custom_attributes"]["plan"]);
+}
+
+#[tokio::test]
+async fn pii_is_hashed() {
+    let document = protect(PiiHandling::Hash, "contact.user.created", contact_json()).await;
+    let data = &document["data"];
+
+    // sha256("joe@example.com"), the same for every record about the contact
+    assert_eq!(
+        "2481f36dfc515ca76451aaadf1399026942a01ee50c6d0a61988b43cef039bc2",
+        data["email"]
+    );
+    for field in ["location", "avatar", "social_profiles"] {
+        assert_eq!(Some(64), data[field].as_str().map(str::len), "{field}");
+    }
+    assert_eq!("5ba682d23d7cf92bef87bfd4", data["id"]);
+}
+
+#[tokio::test]
+async fn pii_of_tagged_contacts_is_protected() {
+    let document = protect(
+        PiiHandling::Redact,
+        "contact.user.tag.created",
+        contact_tag_json(),
+    )
+    .await;
+
+    assert!(document["data"]["contact"].get("email").is_none());
+    assert_eq!("VIP", document["data"]["tag"]["name"]);
+}
+
+#[test]
+fn pii_config() {
+    let routes = "[[routes]]\ntopics = [\"*\"]\n";
+
+    let config = PipelineConfig::parse(&format!("[pii]\nhandling = \"hash\"\n{routes}")).unwrap();
+    let pii = config.pii.unwrap();
+    assert_eq!(PiiHandling::Hash, pii.handling);
+    assert_eq!(Contact::PII_FIELDS, pii.fields);
+
+    let config = PipelineConfig::parse(&format!(
+        "[pii]\nhandling = \"redact\"\nfields = [\"email\"]\n{routes}"
+    ))
+    .unwrap();
+    assert_eq!(vec![s("email")], config.pii.unwrap().fields);
+
+    assert!(PipelineConfig::parse(routes).unwrap().pii.is_none());
+    assert!(PipelineConfig::parse(&format!("[pii]\nhandling = \"mask\"\n{routes}")).is_err());
+    assert!(PipelineConfig::parse(&format!(
+        "[pii]\nhandling = \"hash\"\nfields = [\"\"]\n{routes}"
+    ))
+    .is_err());
+}
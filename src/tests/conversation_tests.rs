@@ -2,11 +2,11 @@ use super::{dt, s};
 use std::collections::HashMap;
 
 use crate::domain::{
+    company::Company,
     conversation::{Conversation, Tag, *},
     notification::Notification,
-    DateTime,
+    topic::Topic,
 };
-use chrono::serde::ts_seconds;
 
 use serde::Deserialize;
 
@@ -15,22 +15,6 @@ const NOTIFICATION_JSON: &str = include_str!("./data_files/notification.json");
 
 #[test]
 fn notification_test() {
-    #[derive(Debug, Deserialize, PartialEq, Eq)]
-    struct Company {
-        #[serde(rename = "type")]
-        typ: String,
-        id: String,
-        name: String,
-        company_id: String,
-        #[serde(deserialize_with = "ts_seconds::deserialize")]
-        remote_created_at: DateTime,
-        #[serde(deserialize_with = "ts_seconds::deserialize")]
-        created_at: DateTime,
-        #[serde(deserialize_with = "ts_seconds::deserialize")]
-        updated_at: DateTime,
-        custom_attributes: HashMap<String, String>,
-    }
-
     let notification: Notification<Company> = serde_json::from_str(NOTIFICATION_JSON).unwrap();
 
     assert_eq!(
@@ -38,20 +22,32 @@ fn notification_test() {
         Notification {
             typ: s("notification_event"),
             id: s("notif_ccd8a4d0-f965-11e3-a367-c779cae3e1b3"),
+            app_id: s("a86dr8yl"),
             url: None,
             created_at: dt("2014-02-18T13:48:51Z"),
-            topic: s("company.created"),
+            topic: Topic::from("company.created"),
             delivery_attempts: 1,
             first_sent_at: dt("2014-02-18T13:49:52Z"),
             data: Company {
                 typ: s("company"),
                 id: s("531ee472cce572a6ec000006"),
                 name: s("Blue Sun"),
+                app_id: None,
                 company_id: s("6"),
-                remote_created_at: dt("2014-03-11T09:46:09Z"),
-                created_at: dt("2014-03-11T10:25:06Z"),
-                updated_at: dt("2014-04-07T12:44:18Z"),
+                plan: None,
+                monthly_spend: 0,
+                user_count: 0,
+                session_count: 0,
+                size: None,
+                website: None,
+                industry: None,
+                remote_created_at: Some(dt("2014-03-11T09:46:09Z")),
+                created_at: Some(dt("2014-03-11T10:25:06Z")),
+                updated_at: Some(dt("2014-04-07T12:44:18Z")),
+                last_request_at: None,
                 custom_attributes: HashMap::new(),
+                segments: vec![],
+                tags: vec![],
             },
         }
     )
@@ -110,7 +106,7 @@ fn conversation_deserialization_test() {
                     typ: s("admin"),
                     id: s("274"),
                     name: s("Operator"),
-                    email: s("operator+abcd1234@intercom.io"),
+                    email: Some(s("operator+abcd1234@intercom.io")),
                 },
                 attachments: vec![Attachment {
                     typ: s("upload"),
@@ -132,6 +128,21 @@ fn conversation_deserialization_test() {
                 external_id: Some(s("f3b87a2e09d514c6c2e79b9a")),
             },],
             teammates: vec![],
+            conversation_parts: vec![ConversationPart {
+                typ: s("conversation_part"),
+                id: s("3"),
+                part_type: s("comment"),
+                body: Some(s("<p>Okay!</p>")),
+                created_at: dt("2022-09-19T14:20:23Z"),
+                updated_at: dt("2022-09-19T14:21:00Z"),
+                notified_at: Some(dt("2022-09-19T14:21:00Z")),
+                assigned_to: Some(Reference {
+                    typ: s("contact"),
+                    id: s("1a2b3c"),
+                }),
+                external_id: Some(s("abcd1234")),
+                redacted: false,
+            }],
             custom_attributes: [(s("property2"), s("string")), (s("property1"), s("string")),]
                 .into_iter()
                 .collect(),
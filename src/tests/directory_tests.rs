@@ -0,0 +1,180 @@
+use std::{path::PathBuf, time::Duration};
+
+use aws_lambda_events::sqs::SqsMessage;
+use wiremock::{
+    matchers::{method, path},
+    Mock, MockServer, ResponseTemplate,
+};
+
+use crate::{
+    directory::{Admin, CachedDirectory, DirectorySource, Team},
+    intercom::IntercomClient,
+    workflow::{Decode, ErrorPolicy, Parse, Pipeline, RecordContext, ResolveDirectory},
+};
+
+use super::{conversation_json, conversation_notification, s};
+
+fn directory_file(name: &str, content: serde_json::Value) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("{name}_{}.json", uuid::Uuid::new_v4()));
+    std::fs::write(&path, content.to_string()).unwrap();
+    path
+}
+
+fn directory_json() -> serde_json::Value {
+    serde_json::json!({
+        "admins": [
+            { "type": "admin", "id": "0", "name": "Joe", "email": "joe@example.com" },
+            { "type": "admin", "id": "c3po", "name": "C-3PO", "email": null },
+        ],
+        "teams": [
+            { "type": "team", "id": "5017691", "name": "Support", "admin_ids": [0] },
+        ],
+    })
+}
+
+async fn mount_directory(server: &MockServer, expected_loads: u64) {
+    let json = directory_json();
+    Mock::given(method("GET"))
+        .and(path("/admins"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "type": "admin.list",
+            "admins": json["admins"],
+        })))
+        .expect(expected_loads)
+        .mount(server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/teams"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "type": "team.list",
+            "teams": json["teams"],
+        })))
+        .expect(expected_loads)
+        .mount(server)
+        .await;
+}
+
+fn api_source(server: &MockServer) -> DirectorySource {
+    DirectorySource::Api(IntercomClient::new(server.uri(), "token").unwrap())
+}
+
+#[tokio::test]
+async fn directory_is_loaded_from_the_api() {
+    let server = MockServer::start().await;
+    mount_directory(&server, 1).await;
+
+    let directory = api_source(&server).load().await.unwrap();
+
+    assert_eq!(
+        Some(&Admin {
+            id: s("0"),
+            name: s("Joe"),
+            email: Some(s("joe@example.com")),
+        }),
+        directory.admin("0")
+    );
+    assert_eq!(
+        Some(&Team {
+            id: s("5017691"),
+            name: s("Support"),
+        }),
+        directory.team("5017691")
+    );
+    assert_eq!(None, directory.admin("missing"));
+}
+
+#[tokio::test]
+async fn directory_is_loaded_from_a_file() {
+    let path = directory_file("directory", directory_json());
+
+    let directory = DirectorySource::File(path.clone()).load().await.unwrap();
+    std::fs::remove_file(path).unwrap();
+
+    assert_eq!("C-3PO", directory.admin("c3po").unwrap().name);
+    assert_eq!("Support", directory.team("5017691").unwrap().name);
+}
+
+#[tokio::test]
+async fn directory_is_cached_until_the_ttl() {
+    let server = MockServer::start().await;
+    mount_directory(&server, 1).await;
+    let cached = CachedDirectory::new(api_source(&server), Duration::from_secs(3600));
+
+    let first = cached.get().await.unwrap();
+    let second = cached.get().await.unwrap();
+    assert!(std::sync::Arc::ptr_eq(&first, &second));
+
+    let server = MockServer::start().await;
+    mount_directory(&server, 2).await;
+    let expiring = CachedDirectory::new(api_source(&server), Duration::ZERO);
+
+    expiring.get().await.unwrap();
+    expiring.get().await.unwrap();
+}
+
+#[tokio::test]
+async fn stale_directory_is_used_when_reloading_fails() {
+    let path = directory_file("stale_directory", directory_json());
+    let cached = CachedDirectory::new(DirectorySource::File(path.clone()), Duration::ZERO);
+
+    cached.get().await.unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let directory = cached.get().await.unwrap();
+    assert_eq!("Joe", directory.admin("0").unwrap().name);
+
+    let missing = CachedDirectory::new(DirectorySource::File(path), Duration::ZERO);
+    assert!(missing.get().await.is_err());
+}
+
+#[tokio::test]
+async fn failed_reloads_are_retried_on_the_next_call() {
+    let path = directory_file("retried_directory", directory_json());
+    let ttl = Duration::from_millis(50);
+    let cached = CachedDirectory::new(DirectorySource::File(path.clone()), ttl);
+
+    cached.get().await.unwrap();
+    tokio::time::sleep(ttl).await;
+    std::fs::remove_file(&path).unwrap();
+
+    let stale = cached.get().await.unwrap();
+    assert_eq!("Joe", stale.admin("0").unwrap().name);
+
+    let mut json = directory_json();
+    json["admins"][0]["name"] = "Joseph".into();
+    std::fs::write(&path, json.to_string()).unwrap();
+
+    let reloaded = cached.get().await.unwrap();
+    assert_eq!("Joseph", reloaded.admin("0").unwrap().name);
+}
+
+#[tokio::test]
+async fn ids_are_resolved_to_names() {
+    let path = directory_file("resolve_directory", directory_json());
+    let cached = CachedDirectory::new(DirectorySource::File(path.clone()), Duration::ZERO);
+    let pipeline = Pipeline::new()
+        .with_stage(Decode)
+        .with_stage(Parse)
+        .with_stage(ResolveDirectory::new(cached, ErrorPolicy::Fail));
+
+    let mut conversation = conversation_json();
+    conversation["teammates"] = serde_json::json!({
+        "type": "admin.list",
+        "teammates": [{ "type": "admin", "id": "c3po" }, { "type": "admin", "id": "unknown" }],
+    });
+    let mut cx = RecordContext::new(&SqsMessage {
+        body: Some(conversation_notification(conversation)),
+        ..Default::default()
+    });
+
+    pipeline.run(&mut cx).await.unwrap();
+    std::fs::remove_file(path).unwrap();
+
+    let data = &cx.output.unwrap()["data"];
+    assert_eq!("Joe", data["admin_assignee"]["name"]);
+    assert_eq!("joe@example.com", data["admin_assignee"]["email"]);
+    assert_eq!("Support", data["team_assignee"]["name"]);
+    assert_eq!("C-3PO", data["statistics"]["last_closed_by"]["name"]);
+    assert_eq!("C-3PO", data["teammates"][0]["name"]);
+    assert!(data["teammates"][1].get("name").is_none());
+}
@@ -0,0 +1,123 @@
+use aws_lambda_events::sqs::SqsMessage;
+use wiremock::{matchers::method, Mock, MockServer, ResponseTemplate};
+
+use crate::{
+    config::OutputFormat,
+    domain::timestamp::TimestampFormat,
+    envelope::{Envelope, HANDLER_VERSION, SCHEMA_VERSION},
+    workflow::{Decode, Destination, Parse, Pipeline, RecordContext, S3Sink},
+};
+
+use super::{conversation_json, conversation_notification, dt, s, s3_client};
+
+/// The shape of each schema version, changing one means incrementing the version
+const ENVELOPE_V1_JSON: &str = include_str!("./data_files/envelope_v1.json");
+
+async fn parsed_record() -> RecordContext {
+    let pipeline = Pipeline::new().with_stage(Decode).with_stage(Parse);
+    let mut cx = RecordContext::new(&SqsMessage {
+        message_id: Some(s("059f36b4-87a3-44ab-83d2-661975830a7d")),
+        body: Some(conversation_notification(conversation_json())),
+        ..Default::default()
+    });
+    cx.received_at = dt("2024-06-27T12:57:45Z");
+
+    pipeline.run(&mut cx).await.unwrap();
+    cx
+}
+
+#[tokio::test]
+async fn envelope_v1_shape() {
+    let cx = parsed_record().await;
+    let mut envelope = serde_json::to_value(cx.envelope().unwrap()).unwrap();
+    // The only value that changes from one release to the next
+    envelope["handler_version"] = "0.0.0".into();
+
+    let expected: serde_json::Value = serde_json::from_str(ENVELOPE_V1_JSON).unwrap();
+    assert_eq!(1, SCHEMA_VERSION, "add a fixture for the new version");
+    assert_eq!(expected, envelope);
+}
+
+#[tokio::test]
+async fn envelopes_describe_the_record() {
+    let cx = parsed_record().await;
+    let envelope = cx.envelope().unwrap();
+
+    assert_eq!(SCHEMA_VERSION, envelope.schema_version);
+    assert_eq!(env!("CARGO_PKG_VERSION"), HANDLER_VERSION);
+    assert_eq!(HANDLER_VERSION, envelope.handler_version);
+    assert_eq!(dt("2024-06-27T12:57:45Z"), envelope.received_at);
+    assert_eq!(TimestampFormat::Rfc3339, envelope.timestamp_format);
+    assert_eq!(
+        Some(s("059f36b4-87a3-44ab-83d2-661975830a7d")),
+        envelope.message_id
+    );
+    assert!(envelope.source_topic == "conversation.admin.closed");
+    assert_eq!("1295", envelope.notification["data"]["id"]);
+}
+
+#[tokio::test]
+async fn envelopes_wrap_the_transformed_output() {
+    let mut cx = parsed_record().await;
+    cx.output = Some(serde_json::json!({ "labels": ["vip"] }));
+
+    assert_eq!(
+        serde_json::json!({ "labels": ["vip"] }),
+        cx.envelope().unwrap().notification
+    );
+}
+
+#[test]
+fn envelopes_can_be_read_back() {
+    let envelope: Envelope<serde_json::Value> = serde_json::from_str(ENVELOPE_V1_JSON).unwrap();
+
+    assert_eq!(1, envelope.schema_version);
+    assert!(envelope.source_topic == "conversation.admin.closed");
+    assert_eq!("notif_1", envelope.notification["id"]);
+
+    // Written before the timestamp format was recorded
+    let mut document: serde_json::Value = serde_json::from_str(ENVELOPE_V1_JSON).unwrap();
+    document.as_object_mut().unwrap().remove("timestamp_format");
+    let envelope: Envelope<serde_json::Value> = serde_json::from_value(document).unwrap();
+    assert_eq!(TimestampFormat::Rfc3339, envelope.timestamp_format);
+}
+
+#[tokio::test]
+async fn records_are_written_in_envelopes() {
+    let server = MockServer::start().await;
+    Mock::given(method("PUT"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&server)
+        .await;
+
+    let mut cx = parsed_record().await;
+    let destination = |key: &str, document| Destination {
+        bucket: s("output"),
+        key: key.into(),
+        format: OutputFormat::Json,
+        document,
+    };
+    cx.destinations = vec![
+        destination("record.json", None),
+        destination("fact.json", Some(serde_json::json!({ "fact": true }))),
+    ];
+    Pipeline::new()
+        .with_stage(S3Sink::new(s3_client(&server.uri())))
+        .run(&mut cx)
+        .await
+        .unwrap();
+
+    let requests = server.received_requests().await.unwrap();
+    let body = |key: &str| -> serde_json::Value {
+        let request = requests
+            .iter()
+            .find(|x| x.url.path() == format!("/output/{key}"))
+            .unwrap();
+        serde_json::from_slice(&request.body).unwrap()
+    };
+    assert_eq!(1, body("record.json")["schema_version"]);
+    assert_eq!("1295", body("record.json")["notification"]["data"]["id"]);
+    // Streams derived from the record have shapes of their own
+    assert_eq!(serde_json::json!({ "fact": true }), body("fact.json"));
+}
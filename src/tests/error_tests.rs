@@ -0,0 +1,119 @@
+use aws_lambda_events::sqs::{BatchItemFailure, SqsMessage};
+
+use crate::{
+    batch_response, deserialize_notification,
+    error::{path_pattern, Error},
+};
+
+use super::{conversation_json, conversation_notification, s};
+
+fn record(message_id: &str) -> SqsMessage {
+    SqsMessage {
+        message_id: Some(s(message_id)),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn deserialization_error_reports_path() {
+    let mut conversation = conversation_json();
+    conversation["statistics"]["first_close_at"] = serde_json::json!("yesterday");
+
+    let error = deserialize_notification(&conversation_notification(conversation)).unwrap_err();
+
+    match &error {
+        Error::Deserialization { path, found, .. } => {
+            assert_eq!("data.item.statistics.first_close_at", path);
+            assert_eq!("string", *found);
+        }
+        other => panic!("expected deserialization error, got {other:?}"),
+    }
+    assert!(!error.is_retryable());
+}
+
+#[test]
+fn deserialization_error_reports_enum_path() {
+    let mut conversation = conversation_json();
+    conversation["ai_agent"]["resolution_state"] = serde_json::json!(42);
+
+    let error = deserialize_notification(&conversation_notification(conversation)).unwrap_err();
+
+    match error {
+        Error::Deserialization { path, found, .. } => {
+            assert_eq!("data.item.ai_agent.resolution_state", path);
+            assert_eq!("number", found);
+        }
+        other => panic!("expected deserialization error, got {other:?}"),
+    }
+}
+
+#[test]
+fn deserialization_error_for_invalid_json() {
+    match deserialize_notification("{\"type\": ").unwrap_err() {
+        Error::Deserialization { found, .. } => assert_eq!("invalid json", found),
+        other => panic!("expected deserialization error, got {other:?}"),
+    }
+}
+
+#[test]
+fn path_pattern_removes_indices() {
+    assert_eq!(
+        "data.item.tags[].applied_at",
+        path_pattern("data.item.tags[3].applied_at")
+    );
+    assert_eq!(
+        "data.item.statistics.first_close_at",
+        path_pattern("data.item.statistics.first_close_at")
+    );
+}
+
+#[test]
+fn retryable_classification() {
+    assert!(Error::sink_write("bucket", "throttled").is_retryable());
+    assert!(!Error::configuration("OUTPUT_BUCKET", "not set").is_retryable());
+    assert!(!Error::SignatureVerification(s("mismatch")).is_retryable());
+}
+
+#[test]
+fn only_retryable_failures_reported() {
+    let (ok, retry, permanent) = (record("ok"), record("retry"), record("permanent"));
+
+    let results = [
+        (&ok, Ok(())),
+        (&retry, Err(Error::sink_write("bucket", "throttled"))),
+        (
+            &permanent,
+            Err(Error::configuration("OUTPUT_BUCKET", "not set")),
+        ),
+    ];
+
+    assert_eq!(
+        vec![BatchItemFailure {
+            item_identifier: s("retry")
+        }],
+        batch_response(&results).unwrap().batch_item_failures
+    );
+}
+
+#[test]
+fn retryable_failures_without_a_message_id_fail_the_batch() {
+    let (ok, unidentified) = (record("ok"), SqsMessage::default());
+
+    let results = [
+        (&ok, Ok(())),
+        (
+            &unidentified,
+            Err(Error::configuration("OUTPUT_BUCKET", "not set")),
+        ),
+    ];
+    assert!(batch_response(&results)
+        .unwrap()
+        .batch_item_failures
+        .is_empty());
+
+    let results = [
+        (&ok, Ok(())),
+        (&unidentified, Err(Error::sink_write("bucket", "throttled"))),
+    ];
+    assert!(batch_response(&results).is_err());
+}
@@ -0,0 +1,363 @@
+use std::time::Duration;
+
+use aws_lambda_events::sqs::SqsMessage;
+use chrono::TimeZone;
+use reqwest::header::{HeaderMap, HeaderValue};
+use wiremock::{
+    matchers::{header, method, path},
+    Mock, MockServer, ResponseTemplate,
+};
+
+use crate::{
+    config::PipelineConfig,
+    domain::timestamp::TimestampFormat,
+    error::Error,
+    intercom::{IntercomClient, RateLimit},
+    workflow::{
+        merge_missing, merge_over, Decode, Enrich, ErrorPolicy, Parse, Pipeline, RecordContext,
+    },
+};
+
+use super::{conversation_json, conversation_notification};
+
+const CONVERSATION_PATH: &str = "/conversations/1295";
+const CONTACT_PATH: &str = "/contacts/5ba682d23d7cf92bef87bfd4";
+
+fn client(server: &MockServer) -> IntercomClient {
+    IntercomClient::new(server.uri(), "token")
+        .unwrap()
+        .with_max_retries(2)
+        .with_backoff(Duration::from_millis(1))
+}
+
+fn enrich_pipeline(server: &MockServer, contacts: bool, on_error: ErrorPolicy) -> Pipeline {
+    Pipeline::new()
+        .with_stage(Decode)
+        .with_stage(Parse)
+        .with_stage(Enrich::new(
+            client(server),
+            vec!["conversation.*".into()],
+            contacts,
+            on_error,
+        ))
+}
+
+fn context() -> RecordContext {
+    let mut conversation = conversation_json();
+    conversation["waiting_since"] = serde_json::Value::Null;
+
+    RecordContext::new(&SqsMessage {
+        body: Some(conversation_notification(conversation)),
+        ..Default::default()
+    })
+}
+
+/// The conversation as the REST API returns it, with every part and their message bodies
+fn api_conversation() -> serde_json::Value {
+    let mut conversation = conversation_json();
+    conversation["title"] = "Title from the api".into();
+    conversation["waiting_since"] = 1663597300.into();
+    let parts = &mut conversation["conversation_parts"];
+    let mut part = parts["conversation_parts"][0].clone();
+    part["id"] = "4".into();
+    part["body"] = "<p>Hello</p>".into();
+    parts["conversation_parts"]
+        .as_array_mut()
+        .unwrap()
+        .push(part);
+    conversation
+}
+
+async fn mock_api(server: &MockServer) {
+    Mock::given(method("GET"))
+        .and(path(CONVERSATION_PATH))
+        .and(header("Authorization", "Bearer token"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(api_conversation()))
+        .expect(1)
+        .mount(server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path(CONTACT_PATH))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "type": "contact",
+            "id": "5ba682d23d7cf92bef87bfd4",
+            "email": "joe@example.com",
+            "created_at": 1663597300,
+        })))
+        .expect(1)
+        .mount(server)
+        .await;
+}
+
+#[tokio::test]
+async fn conversations_and_contacts_are_merged() {
+    let server = MockServer::start().await;
+    mock_api(&server).await;
+
+    let mut cx = context();
+    enrich_pipeline(&server, true, ErrorPolicy::Fail)
+        .run(&mut cx)
+        .await
+        .unwrap();
+
+    let data = &cx.output.unwrap()["data"];
+    assert_eq!("2022-09-19T14:21:40Z", data["waiting_since"]);
+    assert_eq!("Title from the api", data["title"]);
+    assert_eq!("joe@example.com", data["contacts"][0]["email"]);
+    // Values only the webhook has are kept
+    assert_eq!(
+        "f3b87a2e09d514c6c2e79b9a",
+        data["contacts"][0]["external_id"]
+    );
+}
+
+#[tokio::test]
+async fn enrichment_only_adds_what_the_model_writes() {
+    let server = MockServer::start().await;
+    mock_api(&server).await;
+
+    let mut cx = context();
+    enrich_pipeline(&server, true, ErrorPolicy::Fail)
+        .with_timestamps(TimestampFormat::EpochSeconds)
+        .run(&mut cx)
+        .await
+        .unwrap();
+
+    let data = &cx.output.unwrap()["data"];
+    assert!(data["source"].get("body").is_none());
+    assert!(data["source"].get("author").is_none());
+    assert!(data["ai_agent"].get("content_sources").is_none());
+    assert!(data["conversation_parts"][0].get("body").is_none());
+    assert!(data["conversation_parts"][0].get("author").is_none());
+    assert_eq!(1663597300, data["waiting_since"]);
+    assert_eq!(1663597300, data["contacts"][0]["created_at"]);
+}
+
+#[tokio::test]
+async fn enriched_contacts_are_protected() {
+    let server = MockServer::start().await;
+    mock_api(&server).await;
+
+    let pii = PipelineConfig::parse("[pii]\nhandling = \"hash\"\n[[routes]]\ntopics = [\"*\"]")
+        .unwrap()
+        .pii
+        .unwrap();
+    let enrich = Enrich::new(
+        client(&server),
+        vec!["conversation.*".into()],
+        true,
+        ErrorPolicy::Fail,
+    )
+    .with_pii(pii.handling, pii.fields);
+
+    let mut cx = context();
+    Pipeline::new()
+        .with_stage(Decode)
+        .with_stage(Parse)
+        .with_stage(enrich)
+        .run(&mut cx)
+        .await
+        .unwrap();
+
+    let output = cx.output.unwrap();
+    let contact = &output["data"]["contacts"][0];
+    assert_eq!(64, contact["email"].as_str().unwrap().len());
+    assert!(!output.to_string().contains("joe@example.com"));
+}
+
+#[tokio::test]
+async fn truncated_conversation_parts_are_restored() {
+    let server = MockServer::start().await;
+    mock_api(&server).await;
+
+    // The webhook carries one of the two parts
+    let mut cx = context();
+    Pipeline::new()
+        .with_stage(Decode)
+        .with_stage(Parse)
+        .run(&mut cx)
+        .await
+        .unwrap();
+    assert_eq!(
+        1,
+        cx.document().unwrap()["data"]["conversation_parts"]
+            .as_array()
+            .unwrap()
+            .len()
+    );
+
+    let mut cx = context();
+    enrich_pipeline(&server, true, ErrorPolicy::Fail)
+        .run(&mut cx)
+        .await
+        .unwrap();
+
+    let parts = &cx.output.unwrap()["data"]["conversation_parts"];
+    assert_eq!(
+        serde_json::json!(["3", "4"]),
+        serde_json::json!([parts[0]["id"], parts[1]["id"]])
+    );
+}
+
+#[tokio::test]
+async fn server_errors_are_retried() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(502))
+        .up_to_n_times(2)
+        .expect(2)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(api_conversation()))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let conversation = client(&server).conversation("1295").await.unwrap();
+    assert_eq!("1295", conversation["id"]);
+}
+
+#[tokio::test]
+async fn retries_are_limited() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(503))
+        .expect(3)
+        .mount(&server)
+        .await;
+
+    let error = client(&server).conversation("1295").await.unwrap_err();
+    assert!(matches!(
+        error,
+        Error::IntercomApi {
+            status: Some(503),
+            ..
+        }
+    ));
+    assert!(error.is_retryable());
+}
+
+#[tokio::test]
+async fn client_errors_are_not_retried() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(404).set_body_string("not found"))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let error = client(&server).conversation("1295").await.unwrap_err();
+    assert!(matches!(
+        error,
+        Error::IntercomApi {
+            status: Some(404),
+            ..
+        }
+    ));
+    assert!(!error.is_retryable());
+}
+
+#[tokio::test]
+async fn rate_limited_requests_wait_for_the_reset() {
+    let server = MockServer::start().await;
+    let reset = (chrono::Utc::now() + chrono::Duration::seconds(1)).timestamp();
+    Mock::given(method("GET"))
+        .respond_with(
+            ResponseTemplate::new(429)
+                .insert_header("X-RateLimit-Remaining", "0")
+                .insert_header("X-RateLimit-Reset", reset.to_string().as_str()),
+        )
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("X-RateLimit-Remaining", "999")
+                .insert_header("X-RateLimit-Reset", reset.to_string().as_str())
+                .set_body_json(api_conversation()),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let conversation = client(&server).conversation("1295").await.unwrap();
+    assert_eq!("1295", conversation["id"]);
+    assert!(chrono::Utc::now().timestamp() >= reset);
+}
+
+#[tokio::test]
+async fn enrichment_failures_can_be_skipped() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(401))
+        .mount(&server)
+        .await;
+
+    let mut cx = context();
+    assert!(enrich_pipeline(&server, false, ErrorPolicy::Fail)
+        .run(&mut cx)
+        .await
+        .is_err());
+
+    let mut cx = context();
+    enrich_pipeline(&server, false, ErrorPolicy::Skip)
+        .run(&mut cx)
+        .await
+        .unwrap();
+    assert!(cx.output.is_none());
+}
+
+#[test]
+fn rate_limit_headers() {
+    let mut headers = HeaderMap::new();
+    headers.insert("X-RateLimit-Remaining", HeaderValue::from_static("0"));
+    headers.insert("X-RateLimit-Reset", HeaderValue::from_static("1704191430"));
+
+    let rate_limit = RateLimit::from_headers(&headers).unwrap();
+    let reset_at = chrono::Utc.timestamp_opt(1704191430, 0).unwrap();
+    assert_eq!(reset_at, rate_limit.reset_at);
+
+    let now = reset_at - chrono::Duration::seconds(5);
+    assert_eq!(Some(Duration::from_secs(5)), rate_limit.wait(&now));
+    assert_eq!(None, rate_limit.wait(&reset_at));
+
+    let available = RateLimit {
+        remaining: 10,
+        ..rate_limit
+    };
+    assert_eq!(None, available.wait(&now));
+
+    assert!(RateLimit::from_headers(&HeaderMap::new()).is_none());
+}
+
+#[test]
+fn merge_overwrites_values() {
+    let mut target =
+        serde_json::json!({ "id": "1", "title": "kept", "parts": [1], "nested": { "a": 1 } });
+    merge_over(
+        &mut target,
+        serde_json::json!({ "title": null, "parts": [1, 2], "nested": { "a": 2, "b": 3 }, "new": null }),
+    );
+
+    assert_eq!(
+        serde_json::json!({ "id": "1", "title": "kept", "parts": [1, 2], "nested": { "a": 2, "b": 3 } }),
+        target
+    );
+}
+
+#[test]
+fn merge_fills_missing_values() {
+    let mut target = serde_json::json!({ "id": "1", "title": null, "nested": { "a": 1 } });
+    merge_missing(
+        &mut target,
+        serde_json::json!({ "id": "2", "title": "t", "nested": { "a": 2, "b": 3 }, "new": [] }),
+    );
+
+    assert_eq!(
+        serde_json::json!({ "id": "1", "title": "t", "nested": { "a": 1, "b": 3 }, "new": [] }),
+        target
+    );
+}
@@ -1,10 +1,31 @@
+mod admin_tests;
+mod alerts_tests;
+mod company_tests;
+mod config_tests;
+mod contact_tests;
 mod conversation_tests;
+mod directory_tests;
+mod envelope_tests;
+mod error_tests;
+mod intercom_tests;
+mod ping_tests;
+mod quarantine_tests;
+mod raw_tests;
+mod schema_tests;
+mod telemetry_tests;
+mod ticket_tests;
+mod timestamp_tests;
+mod topic_tests;
+mod workflow_tests;
 
 use std::str::FromStr as _;
 
 use chrono::TimeZone;
 
-use crate::domain::DateTime;
+use crate::{
+    config::{KeyFields, KeyTemplate},
+    domain::DateTime,
+};
 
 fn s(s: &str) -> String {
     s.to_string()
@@ -14,12 +35,59 @@ fn dt(dt: &str) -> DateTime {
     chrono::DateTime::from_str(dt).unwrap()
 }
 
+const CONVERSATION_JSON: &str = include_str!("./data_files/conversation.json");
+
+fn conversation_json() -> serde_json::Value {
+    serde_json::from_str(CONVERSATION_JSON).unwrap()
+}
+
+/// Wrap an item in a notification the way Intercom sends it
+fn notification(topic: &str, item: serde_json::Value) -> String {
+    serde_json::json!({
+        "type": "notification_event",
+        "topic": topic,
+        "id": "notif_1",
+        "app_id": "a86dr8yl",
+        "created_at": 1392731331,
+        "delivery_attempts": 1,
+        "first_sent_at": 1392731392,
+        "data": { "item": item }
+    })
+    .to_string()
+}
+
+/// A client for an S3 API mocked at `endpoint_url`
+fn s3_client(endpoint_url: &str) -> aws_sdk_s3::Client {
+    use aws_sdk_s3::config::{BehaviorVersion, Credentials, Region};
+
+    let config = aws_sdk_s3::Config::builder()
+        .behavior_version(BehaviorVersion::latest())
+        .region(Region::new("eu-west-1"))
+        .credentials_provider(Credentials::new("key", "secret", None, None, "test"))
+        .endpoint_url(endpoint_url)
+        .force_path_style(true)
+        .build();
+    aws_sdk_s3::Client::from_conf(config)
+}
+
+fn conversation_notification(conversation: serde_json::Value) -> String {
+    notification("conversation.admin.closed", conversation)
+}
+
 #[test]
 fn file_name_format() {
-    let now = chrono::Utc.with_ymd_and_hms(2024, 01, 02, 10, 30, 00).unwrap();
+    let now = chrono::Utc.with_ymd_and_hms(2024, 1, 2, 10, 30, 0).unwrap();
     let uuid = uuid::uuid!("00000000-0000-0000-0000-ffff00000000");
-    let topic_name = "test.topic";
+    let topic_name = "test.topic".into();
 
     let expected = "20240102_test_topic_00000000-0000-0000-0000-ffff00000000.json";
-    assert_eq!(expected, crate::get_file_name(&now, topic_name, &uuid))
+    let key = KeyTemplate::default().render(&KeyFields {
+        now: &now,
+        topic: &topic_name,
+        notification_id: "notif_1",
+        item_id: "1295",
+        app_id: "a86dr8yl",
+        uuid: &uuid,
+    });
+    assert_eq!(expected, key)
 }
@@ -0,0 +1,90 @@
+use super::{conversation_json, conversation_notification, dt, s};
+
+use aws_lambda_events::sqs::SqsMessage;
+
+use crate::{
+    config::PipelineConfig,
+    deserialize,
+    domain::ping::*,
+    workflow::{Decode, Heartbeats, Parse, Pipeline, RecordContext},
+};
+
+const PING_JSON: &str = include_str!("./data_files/ping.json");
+
+#[test]
+fn ping_deserialization_test() {
+    let ping: PingNotification = deserialize(PING_JSON).expect("Failed to deserialize ping");
+
+    assert_eq!(
+        ping,
+        PingNotification {
+            app_id: Some(s("a86dr8yl")),
+            created_at: dt("2014-02-18T13:48:51Z"),
+            data: Ping {
+                typ: s("ping"),
+                message: Some(s("something something interzen")),
+            },
+        }
+    );
+
+    let heartbeat = Heartbeat::new(&ping, dt("2014-02-18T13:49:00Z"));
+    assert_eq!(
+        serde_json::json!({
+            "app_id": "a86dr8yl",
+            "message": "something something interzen",
+            "sent_at": "2014-02-18T13:48:51Z",
+            "received_at": "2014-02-18T13:49:00Z",
+        }),
+        serde_json::to_value(heartbeat).unwrap()
+    );
+}
+
+fn pipeline() -> Pipeline {
+    Pipeline::new()
+        .with_stage(Decode)
+        .with_stage(Heartbeats::new(None))
+        .with_stage(Parse)
+}
+
+#[tokio::test]
+async fn pings_are_acknowledged() {
+    let mut cx = RecordContext::new(&SqsMessage {
+        body: Some(PING_JSON.into()),
+        ..Default::default()
+    });
+
+    pipeline().run(&mut cx).await.unwrap();
+
+    assert!(cx.raw.is_none());
+    assert!(cx.written.is_empty());
+}
+
+#[tokio::test]
+async fn other_topics_are_parsed() {
+    let mut cx = RecordContext::new(&SqsMessage {
+        body: Some(conversation_notification(conversation_json())),
+        ..Default::default()
+    });
+
+    pipeline().run(&mut cx).await.unwrap();
+
+    assert!(cx.raw.is_some());
+}
+
+#[test]
+fn heartbeat_config() {
+    let routes = "[[routes]]\ntopics = [\"*\"]\nsinks = [{ type = \"s3\", bucket = \"output\" }]\n";
+
+    assert!(PipelineConfig::parse(routes).unwrap().heartbeat.is_none());
+
+    let config = PipelineConfig::parse(&format!("[heartbeat]\n{routes}"))
+        .unwrap()
+        .with_default_bucket(Some("health"))
+        .unwrap();
+    let heartbeat = config.heartbeat.unwrap();
+    assert_eq!(Some(s("health")), heartbeat.bucket);
+    assert_eq!("heartbeats/", heartbeat.prefix);
+
+    let config = PipelineConfig::parse(&format!("[heartbeat]\n{routes}")).unwrap();
+    assert!(config.with_default_bucket(None).is_err());
+}
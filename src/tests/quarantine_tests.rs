@@ -0,0 +1,227 @@
+use aws_lambda_events::sqs::SqsMessage;
+use wiremock::{
+    matchers::{method, path},
+    Mock, MockServer, ResponseTemplate,
+};
+
+use crate::{
+    error::Error,
+    quarantine::{delivery_attempts, receive_count, should_quarantine, Failure, Quarantine},
+};
+
+use super::{dt, s, s3_client};
+
+const HISTORY_PATH: &str = "/quarantine-bucket/quarantine/failures/msg_1.json";
+const RECORD_PATH: &str = "/quarantine-bucket/quarantine/records/msg_1.json";
+
+const NO_SUCH_KEY: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Error><Code>NoSuchKey</Code><Message>The specified key does not exist.</Message></Error>"#;
+
+fn record(receive_count: Option<&str>, delivery_attempts: Option<u32>) -> SqsMessage {
+    SqsMessage {
+        message_id: Some(s("msg_1")),
+        attributes: receive_count
+            .map(|count| (s("ApproximateReceiveCount"), s(count)))
+            .into_iter()
+            .collect(),
+        body: delivery_attempts.map(|attempts| {
+            serde_json::json!({ "type": "notification_event", "delivery_attempts": attempts })
+                .to_string()
+        }),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn receive_count_from_record() {
+    assert_eq!(3, receive_count(&record(Some("3"), None)));
+    assert_eq!(2, receive_count(&record(Some("2"), Some(4))));
+    assert_eq!(1, receive_count(&record(None, Some(4))));
+    assert_eq!(1, receive_count(&record(None, None)));
+    assert_eq!(1, receive_count(&record(Some("many"), None)));
+
+    assert_eq!(Some(4), delivery_attempts(&record(Some("2"), Some(4))));
+    assert_eq!(None, delivery_attempts(&record(Some("2"), None)));
+}
+
+#[test]
+fn quarantine_threshold() {
+    let retryable = Error::sink_write("bucket", "throttled");
+    let permanent = Error::configuration("OUTPUT_BUCKET", "not set");
+
+    assert!(!should_quarantine(&retryable, 1, 5));
+    assert!(!should_quarantine(&retryable, 4, 5));
+    assert!(should_quarantine(&retryable, 5, 5));
+    assert!(should_quarantine(&permanent, 1, 5));
+}
+
+async fn mock(server: &MockServer, verb: &str, at: &str, response: ResponseTemplate) {
+    Mock::given(method(verb))
+        .and(path(at))
+        .respond_with(response)
+        .mount(server)
+        .await;
+}
+
+/// The JSON bodies written to `at`
+async fn written(server: &MockServer, at: &str) -> Vec<serde_json::Value> {
+    server
+        .received_requests()
+        .await
+        .unwrap()
+        .into_iter()
+        .filter(|x| x.method.as_str() == "PUT" && x.url.path() == at)
+        .map(|x| serde_json::from_slice(&x.body).unwrap())
+        .collect()
+}
+
+async fn deleted(server: &MockServer, at: &str) -> bool {
+    server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .any(|x| x.method.as_str() == "DELETE" && x.url.path() == at)
+}
+
+fn failure(receive_count: u32) -> Failure {
+    Failure {
+        failed_at: dt("2024-06-27T12:54:40Z"),
+        receive_count,
+        delivery_attempts: None,
+        retryable: true,
+        error: s("failed to write to output: throttled"),
+    }
+}
+
+#[tokio::test]
+async fn retryable_failures_are_recorded_and_retried() {
+    let server = MockServer::start().await;
+    mock(
+        &server,
+        "GET",
+        HISTORY_PATH,
+        ResponseTemplate::new(404).set_body_string(NO_SUCH_KEY),
+    )
+    .await;
+    mock(&server, "PUT", HISTORY_PATH, ResponseTemplate::new(200)).await;
+
+    let client = s3_client(&server.uri());
+    let quarantine = Quarantine::new(&client, s("quarantine-bucket"), s("quarantine/"), 3);
+
+    // Intercom's fifth attempt at the webhook is still the handler's first
+    let result = quarantine
+        .handle_failure(
+            &record(Some("1"), Some(5)),
+            Error::sink_write("output", "throttled"),
+        )
+        .await;
+    assert!(matches!(result, Err(Error::SinkWrite { .. })));
+
+    let history = written(&server, HISTORY_PATH).await;
+    assert_eq!(1, history.len());
+    let failures: Vec<Failure> = serde_json::from_value(history[0].clone()).unwrap();
+    assert_eq!(1, failures.len());
+    assert_eq!(1, failures[0].receive_count);
+    assert_eq!(Some(5), failures[0].delivery_attempts);
+    assert!(failures[0].retryable);
+
+    assert!(written(&server, RECORD_PATH).await.is_empty());
+    assert!(!deleted(&server, HISTORY_PATH).await);
+}
+
+#[tokio::test]
+async fn records_are_quarantined_with_their_history() {
+    let server = MockServer::start().await;
+    let history = vec![failure(1), failure(2)];
+    mock(
+        &server,
+        "GET",
+        HISTORY_PATH,
+        ResponseTemplate::new(200).set_body_json(&history),
+    )
+    .await;
+    mock(&server, "PUT", RECORD_PATH, ResponseTemplate::new(200)).await;
+    mock(&server, "DELETE", HISTORY_PATH, ResponseTemplate::new(204)).await;
+
+    let client = s3_client(&server.uri());
+    let quarantine = Quarantine::new(&client, s("quarantine-bucket"), s("quarantine/"), 3);
+
+    quarantine
+        .handle_failure(
+            &record(Some("3"), Some(1)),
+            Error::sink_write("output", "throttled"),
+        )
+        .await
+        .unwrap();
+
+    let records = written(&server, RECORD_PATH).await;
+    assert_eq!(1, records.len());
+    assert_eq!("msg_1", records[0]["message_id"]);
+    assert_eq!(3, records[0]["receive_count"]);
+    assert_eq!(1, records[0]["delivery_attempts"]);
+    assert_eq!("3", records[0]["attributes"]["ApproximateReceiveCount"]);
+    assert!(records[0]["body"].is_string());
+
+    let failures: Vec<Failure> = serde_json::from_value(records[0]["failures"].clone()).unwrap();
+    assert_eq!(3, failures.len());
+    assert_eq!(history, failures[..2]);
+    assert_eq!(3, failures[2].receive_count);
+
+    assert!(written(&server, HISTORY_PATH).await.is_empty());
+    assert!(deleted(&server, HISTORY_PATH).await);
+}
+
+#[tokio::test]
+async fn permanent_failures_are_quarantined_immediately() {
+    let server = MockServer::start().await;
+    mock(
+        &server,
+        "GET",
+        HISTORY_PATH,
+        ResponseTemplate::new(404).set_body_string(NO_SUCH_KEY),
+    )
+    .await;
+    mock(&server, "PUT", RECORD_PATH, ResponseTemplate::new(200)).await;
+    mock(&server, "DELETE", HISTORY_PATH, ResponseTemplate::new(204)).await;
+
+    let client = s3_client(&server.uri());
+    let quarantine = Quarantine::new(&client, s("quarantine-bucket"), s("quarantine/"), 3);
+
+    quarantine
+        .handle_failure(
+            &record(Some("1"), Some(1)),
+            Error::Validation(s("unsupported topic")),
+        )
+        .await
+        .unwrap();
+
+    let records = written(&server, RECORD_PATH).await;
+    assert_eq!(1, records.len());
+    assert_eq!(false, records[0]["failures"][0]["retryable"]);
+}
+
+#[tokio::test]
+async fn failures_are_retried_when_the_quarantine_cannot_be_written() {
+    let server = MockServer::start().await;
+    mock(
+        &server,
+        "GET",
+        HISTORY_PATH,
+        ResponseTemplate::new(404).set_body_string(NO_SUCH_KEY),
+    )
+    .await;
+    mock(&server, "PUT", RECORD_PATH, ResponseTemplate::new(500)).await;
+
+    let client = s3_client(&server.uri());
+    let quarantine = Quarantine::new(&client, s("quarantine-bucket"), s("quarantine/"), 3);
+
+    let result = quarantine
+        .handle_failure(
+            &record(Some("1"), None),
+            Error::Validation(s("unsupported topic")),
+        )
+        .await;
+    assert!(result.unwrap_err().is_retryable());
+    assert!(!deleted(&server, HISTORY_PATH).await);
+}
@@ -0,0 +1,184 @@
+use aws_lambda_events::sqs::SqsMessage;
+use wiremock::{
+    matchers::{method, path_regex},
+    Mock, MockServer, ResponseTemplate,
+};
+
+use std::sync::Arc;
+
+use crate::{
+    config::{KeyTemplate, PipelineConfig, RawArchive},
+    workflow::{
+        ArchiveRaw, Decode, Parse, Pipeline, RecordContext, RouteByTopic, SkipUnmodelled, Validate,
+    },
+};
+
+use super::{conversation_json, conversation_notification, s, s3_client};
+
+fn archive(endpoint_url: &str) -> ArchiveRaw {
+    let key_template: KeyTemplate = RawArchive::DEFAULT_KEY_TEMPLATE.parse().unwrap();
+    ArchiveRaw::new(s3_client(endpoint_url), s("raw-bucket"), key_template)
+}
+
+fn record(message_id: Option<&str>, body: &str) -> RecordContext {
+    let mut cx = RecordContext::new(&SqsMessage {
+        message_id: message_id.map(s),
+        body: Some(body.into()),
+        ..Default::default()
+    });
+    cx.body = cx.record.body.clone();
+    cx
+}
+
+#[test]
+fn raw_keys_are_stable_for_a_message() {
+    let archive = archive("http://localhost");
+    let now = chrono::Utc::now();
+    let body = conversation_notification(conversation_json());
+
+    let key = archive.key(&record(Some("m1"), &body), &now).unwrap();
+    assert!(key.starts_with("raw/"));
+    assert!(key.contains("/conversation_admin_closed_"));
+    assert_eq!(key, archive.key(&record(Some("m1"), &body), &now).unwrap());
+    assert_ne!(key, archive.key(&record(Some("m2"), &body), &now).unwrap());
+    assert_ne!(
+        archive.key(&record(None, &body), &now).unwrap(),
+        archive.key(&record(None, &body), &now).unwrap()
+    );
+}
+
+#[test]
+fn raw_keys_do_not_need_a_notification() {
+    let archive = archive("http://localhost");
+    let key = archive
+        .key(&record(Some("m1"), "not json"), &chrono::Utc::now())
+        .unwrap();
+
+    assert!(key.contains("/unknown_"));
+}
+
+#[tokio::test]
+async fn bodies_are_archived_before_they_are_parsed() {
+    let server = MockServer::start().await;
+    Mock::given(method("PUT"))
+        .and(path_regex("^/raw-bucket/raw/"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let pipeline = Pipeline::new()
+        .with_stage(Decode)
+        .with_stage(Parse)
+        .with_stage(archive(&server.uri()));
+
+    // A body the typed model rejects is still kept byte for byte
+    let body = r#"{"type": "notification_event",  "topic": "conversation.admin.closed"}"#;
+    let mut cx = RecordContext::new(&SqsMessage {
+        message_id: Some(s("m1")),
+        body: Some(body.into()),
+        ..Default::default()
+    });
+
+    assert!(pipeline.run(&mut cx).await.is_err());
+    assert_eq!(1, cx.written.len());
+    assert!(cx.written[0].starts_with("raw/"));
+
+    let requests = server.received_requests().await.unwrap();
+    assert_eq!(body.as_bytes(), requests[0].body);
+}
+
+#[tokio::test]
+async fn items_are_parsed_when_they_are_needed() {
+    let pipeline = Pipeline::new()
+        .with_stage(Decode)
+        .with_stage(Parse)
+        .with_stage(Validate);
+
+    let mut conversation = conversation_json();
+    conversation.as_object_mut().unwrap().remove("created_at");
+    let mut cx = RecordContext::new(&SqsMessage {
+        body: Some(conversation_notification(conversation)),
+        ..Default::default()
+    });
+
+    pipeline.run(&mut cx).await.unwrap();
+    assert_eq!("notif_1", cx.raw.as_ref().unwrap().id);
+    assert!(cx.raw.as_ref().unwrap().data["created_at"].is_null());
+    assert!(cx.parsed().is_none());
+
+    let error = cx.notification().unwrap_err().to_string();
+    assert!(error.contains("created_at"), "{error}");
+    assert!(cx.notification().unwrap_err().to_string().contains(&error));
+    assert!(cx.parsed().is_none());
+}
+
+#[tokio::test]
+async fn unmodelled_topics_are_only_archived() {
+    let server = MockServer::start().await;
+    Mock::given(method("PUT"))
+        .and(path_regex("^/raw-bucket/raw/"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(3)
+        .mount(&server)
+        .await;
+
+    let config = PipelineConfig::parse("[[routes]]\ntopics = [\"*\"]")
+        .unwrap()
+        .with_default_bucket(Some("output"))
+        .unwrap();
+    let routes = Arc::new(config.routes);
+    let pipeline = Pipeline::new()
+        .with_stage(Decode)
+        .with_stage(archive(&server.uri()))
+        .with_stage(Parse)
+        .with_stage(SkipUnmodelled)
+        .with_stage(Validate)
+        .with_stage(RouteByTopic::new(routes));
+
+    for topic in ["visitor.signed_up", "something.new"] {
+        let body = serde_json::json!({
+            "type": "notification_event",
+            "app_id": "a86dr8yl",
+            "id": "notif_1",
+            "topic": topic,
+            "created_at": 1392731331,
+            "delivery_attempts": 1,
+            "first_sent_at": 1392731392,
+            "data": { "item": { "type": "visitor" } },
+        });
+        let mut cx = RecordContext::new(&SqsMessage {
+            message_id: Some(s(topic)),
+            body: Some(body.to_string()),
+            ..Default::default()
+        });
+
+        pipeline.run(&mut cx).await.unwrap();
+        assert_eq!(1, cx.written.len(), "{topic}");
+        assert!(cx.destinations.is_empty(), "{topic}");
+    }
+
+    // Modelled topics are still routed
+    let mut cx = RecordContext::new(&SqsMessage {
+        message_id: Some(s("m1")),
+        body: Some(conversation_notification(conversation_json())),
+        ..Default::default()
+    });
+    pipeline.run(&mut cx).await.unwrap();
+    assert_eq!(1, cx.destinations.len());
+}
+
+#[test]
+fn raw_config() {
+    let config = PipelineConfig::parse(include_str!("../../pipeline.toml"))
+        .unwrap()
+        .with_default_bucket(Some("output"))
+        .unwrap();
+    assert_eq!(Some(s("output")), config.raw.unwrap().bucket);
+
+    let routes = "[[routes]]\ntopics = [\"*\"]\n";
+    assert!(PipelineConfig::parse(routes).unwrap().raw.is_none());
+    assert!(
+        PipelineConfig::parse(&format!("[raw]\nkey_template = \"raw/x.json\"\n{routes}")).is_err()
+    );
+}
@@ -0,0 +1,116 @@
+use std::path::Path;
+
+use schemars::{generate::SchemaSettings, JsonSchema, Schema};
+
+use crate::{
+    alerts::Alert,
+    domain::{
+        admin::{Admin, Availability},
+        company::Company,
+        contact::{Contact, ContactTag},
+        conversation::Conversation,
+        notification::Notification,
+        ping::Heartbeat,
+        ticket::Ticket,
+        timestamp::TimestampFormat,
+    },
+    envelope::Envelope,
+};
+
+/// Schemas describe what is written rather than what is read, so fields that are
+/// never serialized are left out
+fn schema_for<T: JsonSchema>() -> Schema {
+    SchemaSettings::draft2020_12()
+        .for_serialize()
+        .into_generator()
+        .into_root_schema_for::<T>()
+}
+
+/// Every document the handler writes, by the file its schema is committed to under schemas/
+fn schemas() -> Vec<(&'static str, Schema)> {
+    vec![
+        (
+            "conversation.json",
+            schema_for::<Envelope<Notification<Conversation>>>(),
+        ),
+        (
+            "ticket.json",
+            schema_for::<Envelope<Notification<Ticket>>>(),
+        ),
+        (
+            "contact.json",
+            schema_for::<Envelope<Notification<Contact>>>(),
+        ),
+        (
+            "contact_tag.json",
+            schema_for::<Envelope<Notification<ContactTag>>>(),
+        ),
+        (
+            "company.json",
+            schema_for::<Envelope<Notification<Company>>>(),
+        ),
+        ("admin.json", schema_for::<Envelope<Notification<Admin>>>()),
+        ("availability.json", schema_for::<Availability>()),
+        ("heartbeat.json", schema_for::<Heartbeat>()),
+        ("alert.json", schema_for::<Alert>()),
+    ]
+}
+
+/// Run with UPDATE_SCHEMAS=1 to rewrite the committed schemas after changing a type
+#[test]
+fn committed_schemas_are_up_to_date() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("schemas");
+    let update = std::env::var("UPDATE_SCHEMAS").is_ok_and(|x| x == "1");
+
+    for (file, schema) in schemas() {
+        let path = dir.join(file);
+        let generated = serde_json::to_string_pretty(&schema).unwrap() + "\n";
+
+        if update {
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(&path, generated).unwrap();
+            continue;
+        }
+
+        let committed = std::fs::read_to_string(&path).unwrap_or_default();
+        assert!(
+            committed == generated,
+            "schemas/{file} is out of date, run the tests with UPDATE_SCHEMAS=1 to regenerate it"
+        );
+    }
+}
+
+#[test]
+fn skipped_fields_are_absent() {
+    let ticket = serde_json::to_value(schema_for::<Ticket>()).unwrap();
+    let part = &ticket["$defs"]["TicketPart"]["properties"];
+    assert!(part.get("ticket_state").is_some());
+    assert!(part.get("author").is_none());
+    assert!(part.get("body").is_none());
+
+    let conversation = serde_json::to_value(schema_for::<Conversation>()).unwrap();
+    let ai_agent = &conversation["$defs"]["AIAgent"]["properties"];
+    assert!(ai_agent.get("source_type").is_some());
+    assert!(ai_agent.get("content_sources").is_none());
+}
+
+#[test]
+fn timestamps_are_strings() {
+    let schema = serde_json::to_value(schema_for::<Notification<Conversation>>()).unwrap();
+
+    assert_eq!("string", schema["properties"]["created_at"]["type"]);
+    assert_eq!("date-time", schema["properties"]["created_at"]["format"]);
+    assert_eq!("string", schema["properties"]["topic"]["type"]);
+}
+
+#[test]
+fn timestamps_follow_the_format() {
+    let schema = TimestampFormat::EpochMillis
+        .scope(|| serde_json::to_value(schema_for::<Envelope<Notification<Contact>>>()).unwrap());
+
+    assert_eq!("integer", schema["properties"]["received_at"]["type"]);
+    assert_eq!(
+        serde_json::json!(["integer", "null"]),
+        schema["$defs"]["Contact"]["properties"]["created_at"]["type"]
+    );
+}
@@ -0,0 +1,234 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use aws_lambda_events::sqs::{SqsMessage, SqsMessageAttribute};
+use opentelemetry::{
+    trace::{
+        Span as _, SpanId, Status, TraceContextExt, TraceId, TraceResult, Tracer as _,
+        TracerProvider as _,
+    },
+    Context, KeyValue, Value,
+};
+use opentelemetry_sdk::{
+    self as sdk,
+    export::trace::SpanData,
+    trace::{Sampler, SpanProcessor, TracerProvider},
+};
+
+use crate::telemetry::{
+    extract_context, lambda_attributes, sampler, ErrorSpanProcessor, RecordDropped,
+};
+
+use super::s;
+
+fn get<'a>(attributes: &'a [KeyValue], key: &str) -> Option<&'a Value> {
+    attributes
+        .iter()
+        .find(|kv| kv.key.as_str() == key)
+        .map(|kv| &kv.value)
+}
+
+#[test]
+fn lambda_attributes_from_environment() {
+    let env: HashMap<_, _> = [
+        ("AWS_LAMBDA_FUNCTION_NAME", s("webhook-handler")),
+        ("AWS_LAMBDA_FUNCTION_VERSION", s("$LATEST")),
+        (
+            "AWS_LAMBDA_LOG_STREAM_NAME",
+            s("2024/01/01/[$LATEST]abcdef"),
+        ),
+        ("AWS_REGION", s("eu-west-2")),
+        ("AWS_LAMBDA_FUNCTION_MEMORY_SIZE", s("128")),
+    ]
+    .into_iter()
+    .collect();
+
+    let attributes = lambda_attributes(|key| env.get(key).cloned());
+
+    assert_eq!(
+        Some(&Value::from("aws")),
+        get(&attributes, "cloud.provider")
+    );
+    assert_eq!(
+        Some(&Value::from("aws_lambda")),
+        get(&attributes, "cloud.platform")
+    );
+    assert_eq!(
+        Some(&Value::from("webhook-handler")),
+        get(&attributes, "faas.name")
+    );
+    assert_eq!(
+        Some(&Value::from("$LATEST")),
+        get(&attributes, "faas.version")
+    );
+    assert_eq!(
+        Some(&Value::from("2024/01/01/[$LATEST]abcdef")),
+        get(&attributes, "faas.instance")
+    );
+    assert_eq!(
+        Some(&Value::from("eu-west-2")),
+        get(&attributes, "cloud.region")
+    );
+    assert_eq!(
+        Some(&Value::I64(134_217_728)),
+        get(&attributes, "faas.max_memory")
+    );
+}
+
+#[test]
+fn lambda_attributes_outside_lambda() {
+    assert!(lambda_attributes(|_| None).is_empty());
+}
+
+fn string_attribute(value: &str) -> SqsMessageAttribute {
+    SqsMessageAttribute {
+        string_value: Some(s(value)),
+        data_type: Some(s("String")),
+        ..Default::default()
+    }
+}
+
+fn trace_ids(record: &SqsMessage) -> Option<(TraceId, SpanId)> {
+    extract_context(record).map(|cx| {
+        let span = cx.span();
+        let span_context = span.span_context();
+        (span_context.trace_id(), span_context.span_id())
+    })
+}
+
+#[test]
+fn extract_traceparent_from_message_attributes() {
+    let record = SqsMessage {
+        message_attributes: [(
+            s("traceparent"),
+            string_attribute("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"),
+        )]
+        .into_iter()
+        .collect(),
+        ..Default::default()
+    };
+
+    assert_eq!(
+        Some((
+            TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap(),
+            SpanId::from_hex("00f067aa0ba902b7").unwrap(),
+        )),
+        trace_ids(&record)
+    );
+}
+
+#[test]
+fn extract_xray_header_from_system_attribute() {
+    let record = SqsMessage {
+        attributes: [(
+            s("AWSTraceHeader"),
+            s("Root=1-5759e988-bd862e3fe1be46a994272793;Parent=53995c3f42cd8ad8;Sampled=1"),
+        )]
+        .into_iter()
+        .collect(),
+        ..Default::default()
+    };
+
+    assert_eq!(
+        Some((
+            TraceId::from_hex("5759e988bd862e3fe1be46a994272793").unwrap(),
+            SpanId::from_hex("53995c3f42cd8ad8").unwrap(),
+        )),
+        trace_ids(&record)
+    );
+}
+
+#[test]
+fn traceparent_preferred_over_xray_header() {
+    let record = SqsMessage {
+        message_attributes: [(
+            s("traceparent"),
+            string_attribute("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"),
+        )]
+        .into_iter()
+        .collect(),
+        attributes: [(
+            s("AWSTraceHeader"),
+            s("Root=1-5759e988-bd862e3fe1be46a994272793;Parent=53995c3f42cd8ad8;Sampled=1"),
+        )]
+        .into_iter()
+        .collect(),
+        ..Default::default()
+    };
+
+    let (trace_id, _) = trace_ids(&record).unwrap();
+    assert_eq!(
+        TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap(),
+        trace_id
+    );
+}
+
+#[test]
+fn no_context_without_propagation_headers() {
+    assert_eq!(None, trace_ids(&SqsMessage::default()));
+}
+
+#[test]
+fn sampler_from_environment() {
+    assert!(matches!(
+        sampler(None, None).unwrap(),
+        Sampler::ParentBased(_)
+    ));
+    assert!(matches!(
+        sampler(Some("always_off"), None).unwrap(),
+        Sampler::AlwaysOff
+    ));
+    assert!(matches!(
+        sampler(Some("traceidratio"), Some("0.25")).unwrap(),
+        Sampler::TraceIdRatioBased(ratio) if ratio == 0.25
+    ));
+    assert!(matches!(
+        sampler(Some("traceidratio"), None).unwrap(),
+        Sampler::TraceIdRatioBased(ratio) if ratio == 1.0
+    ));
+
+    assert!(sampler(Some("traceidratio"), Some("1.5")).is_err());
+    assert!(sampler(Some("parentbased_traceidratio"), Some("half")).is_err());
+    assert!(sampler(Some("jaeger_remote"), None).is_err());
+}
+
+/// Collects the names of the spans an exporting processor would export
+#[derive(Debug, Default, Clone)]
+struct SampledSpans(Arc<Mutex<Vec<String>>>);
+impl SpanProcessor for SampledSpans {
+    fn on_start(&self, _span: &mut sdk::trace::Span, _cx: &Context) {}
+
+    fn on_end(&self, span: SpanData) {
+        if span.span_context.is_sampled() {
+            self.0.lock().unwrap().push(span.name.into_owned());
+        }
+    }
+
+    fn force_flush(&self) -> TraceResult<()> {
+        Ok(())
+    }
+
+    fn shutdown(&mut self) -> TraceResult<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn failed_spans_kept_when_sampled_out() {
+    let exported = SampledSpans::default();
+    let provider = TracerProvider::builder()
+        .with_span_processor(ErrorSpanProcessor(exported.clone()))
+        .with_config(sdk::trace::config().with_sampler(RecordDropped(Sampler::AlwaysOff)))
+        .build();
+    let tracer = provider.tracer("test");
+
+    tracer.start("succeeded").end();
+
+    let mut failed = tracer.start("failed");
+    failed.set_status(Status::error("push failed"));
+    failed.end();
+
+    assert_eq!(vec![s("failed")], *exported.0.lock().unwrap());
+}
@@ -0,0 +1,258 @@
+use super::{conversation_json, dt, notification, s};
+use std::sync::Arc;
+
+use aws_lambda_events::sqs::SqsMessage;
+
+use crate::{
+    config::PipelineConfig,
+    deserialize_notification,
+    domain::{
+        conversation::{Author, ContactReference, Reference},
+        ticket::*,
+        Item,
+    },
+    error::Error,
+    workflow::{AcceptTopics, Decode, Parse, Pipeline, RecordContext, RouteByTopic},
+};
+
+const TICKET_JSON: &str = include_str!("./data_files/ticket.json");
+
+fn ticket_json() -> serde_json::Value {
+    serde_json::from_str(TICKET_JSON).unwrap()
+}
+
+#[test]
+fn ticket_deserialization_test() {
+    let ticket: Ticket = serde_json::from_str(TICKET_JSON).expect("Failed to deserialize ticket");
+
+    assert_eq!(
+        ticket,
+        Ticket {
+            typ: s("ticket"),
+            id: s("494"),
+            ticket_id: s("22"),
+            category: TicketCategory::Customer,
+            ticket_attributes: [
+                (
+                    s("_default_title_"),
+                    serde_json::json!("Login button does nothing")
+                ),
+                (
+                    s("_default_description_"),
+                    serde_json::json!("Clicking login on the mobile app does not respond")
+                ),
+                (s("severity"), serde_json::json!("High")),
+                (s("affected_users"), serde_json::json!(3)),
+                (s("reproducible"), serde_json::json!(true)),
+            ]
+            .into(),
+            ticket_state: TicketState {
+                typ: s("ticket_state"),
+                id: s("8"),
+                category: TicketStateCategory::InProgress,
+                internal_label: s("In progress"),
+                external_label: s("In progress"),
+            },
+            ticket_type: TicketType {
+                typ: s("ticket_type"),
+                id: s("58"),
+                category: TicketCategory::Customer,
+                name: s("Bug Report"),
+                description: s("Used for tracking bugs"),
+                icon: s("🐞"),
+                workspace_id: s("this_is_an_id_123_a"),
+                ticket_type_attributes: vec![TicketTypeAttribute {
+                    typ: s("ticket_type_attribute"),
+                    id: s("210"),
+                    workspace_id: s("this_is_an_id_123_a"),
+                    name: s("severity"),
+                    description: s("How badly the bug affects users"),
+                    data_type: s("list"),
+                    input_options: Some(serde_json::json!({
+                        "list_options": [
+                            { "id": "1", "label": "Low" },
+                            { "id": "2", "label": "High" }
+                        ]
+                    })),
+                    order: 2,
+                    required_to_create: false,
+                    required_to_create_for_contacts: false,
+                    visible_on_create: true,
+                    visible_to_contacts: true,
+                    default: false,
+                    ticket_type_id: 58,
+                    archived: false,
+                    created_at: dt("2024-06-27T12:54:40Z"),
+                    updated_at: dt("2024-06-27T12:54:40Z"),
+                }],
+                archived: false,
+                created_at: dt("2024-06-27T12:54:40Z"),
+                updated_at: dt("2024-06-27T12:54:40Z"),
+            },
+            contacts: vec![ContactReference {
+                reference: Reference {
+                    typ: s("contact"),
+                    id: s("5ba682d23d7cf92bef87bfd4"),
+                },
+                external_id: Some(s("f3b87a2e09d514c6c2e79b9a")),
+            }],
+            admin_assignee_id: Some(s("991267")),
+            team_assignee_id: Some(s("5017691")),
+            created_at: dt("2024-06-27T12:54:40Z"),
+            updated_at: dt("2024-06-27T12:57:45Z"),
+            open: true,
+            snoozed_until: None,
+            linked_objects: vec![
+                LinkedObject {
+                    typ: s("conversation"),
+                    id: s("1295"),
+                    category: None,
+                },
+                LinkedObject {
+                    typ: s("ticket"),
+                    id: s("495"),
+                    category: Some(TicketCategory::Tracker),
+                },
+            ],
+            ticket_parts: vec![
+                TicketPart {
+                    typ: s("ticket_part"),
+                    id: s("4412"),
+                    part_type: s("ticket_state_updated_by_admin"),
+                    body: None,
+                    previous_ticket_state: Some(TicketStateCategory::Submitted),
+                    ticket_state: TicketStateCategory::InProgress,
+                    created_at: dt("2024-06-27T12:57:45Z"),
+                    updated_at: dt("2024-06-27T12:57:45Z"),
+                    assigned_to: None,
+                    author: Author {
+                        typ: s("admin"),
+                        id: s("991267"),
+                        name: s("Operator"),
+                        email: Some(s("operator+abcd1234@intercom.io")),
+                    },
+                    redacted: false,
+                },
+                TicketPart {
+                    typ: s("ticket_part"),
+                    id: s("4413"),
+                    part_type: s("comment"),
+                    body: Some(s("<p>We are looking into it.</p>")),
+                    previous_ticket_state: Some(TicketStateCategory::InProgress),
+                    ticket_state: TicketStateCategory::InProgress,
+                    created_at: dt("2024-06-27T12:58:00Z"),
+                    updated_at: dt("2024-06-27T12:58:00Z"),
+                    assigned_to: None,
+                    author: Author {
+                        typ: s("bot"),
+                        id: s("991268"),
+                        name: s("Fin"),
+                        email: None,
+                    },
+                    redacted: false,
+                }
+            ],
+            is_shared: true,
+        }
+    )
+}
+
+#[test]
+fn linked_objects_and_parts_are_optional() {
+    let mut json = ticket_json();
+    let fields = json.as_object_mut().unwrap();
+    fields.remove("linked_objects");
+    fields.remove("ticket_parts");
+
+    let ticket: Ticket = serde_json::from_value(json).unwrap();
+    assert!(ticket.linked_objects.is_empty());
+    assert!(ticket.ticket_parts.is_empty());
+}
+
+#[test]
+fn ticket_categories() {
+    let categories: Vec<TicketCategory> =
+        serde_json::from_str(r#"["Customer", "Back-office", "Tracker"]"#).unwrap();
+
+    assert_eq!(
+        vec![
+            TicketCategory::Customer,
+            TicketCategory::BackOffice,
+            TicketCategory::Tracker
+        ],
+        categories
+    );
+}
+
+#[test]
+fn notifications_are_parsed_by_topic() {
+    for topic in [
+        "ticket.created",
+        "ticket.state.updated",
+        "ticket.admin.assigned",
+    ] {
+        let parsed = deserialize_notification(&notification(topic, ticket_json())).unwrap();
+        assert!(matches!(parsed.data, Item::Ticket(_)), "{topic}");
+        assert_eq!("494", parsed.data.id());
+    }
+
+    let parsed = deserialize_notification(&notification(
+        "conversation.user.created",
+        conversation_json(),
+    ))
+    .unwrap();
+    assert!(matches!(parsed.data, Item::Conversation(_)));
+
+    // A ticket under a conversation topic is reported at the first field that differs
+    match deserialize_notification(&notification("conversation.user.created", ticket_json())) {
+        Err(Error::Deserialization { path, .. }) => assert!(path.starts_with("data.item.")),
+        other => panic!("expected deserialization error, got {other:?}"),
+    }
+
+    assert!(matches!(
+        deserialize_notification(&notification("visitor.signed_up", ticket_json())),
+        Err(Error::Validation(_))
+    ));
+}
+
+#[test]
+fn tickets_serialize_as_the_item() {
+    let parsed = deserialize_notification(&notification("ticket.created", ticket_json())).unwrap();
+    let document = serde_json::to_value(&parsed).unwrap();
+
+    assert_eq!("ticket", document["data"]["type"]);
+    assert_eq!("in_progress", document["data"]["ticket_state"]["category"]);
+    assert_eq!("Back-office", serde_json::json!(TicketCategory::BackOffice));
+    assert!(document["data"]["ticket_parts"][0].get("author").is_none());
+    assert!(document["data"]["ticket_parts"][0].get("body").is_none());
+}
+
+#[tokio::test]
+async fn tickets_are_routed() {
+    let config = PipelineConfig::parse(
+        r#"
+[[routes]]
+topics = ["ticket.*"]
+key_template = "tickets/{topic_path}/{item_id}_{uuid}.json"
+sinks = [{ type = "s3", bucket = "tickets" }]
+"#,
+    )
+    .unwrap();
+    let routes = Arc::new(config.routes);
+    let pipeline = Pipeline::new()
+        .with_stage(Decode)
+        .with_stage(AcceptTopics::new(routes.clone()))
+        .with_stage(Parse)
+        .with_stage(RouteByTopic::new(routes));
+
+    let mut cx = RecordContext::new(&SqsMessage {
+        body: Some(notification("ticket.state.updated", ticket_json())),
+        ..Default::default()
+    });
+    pipeline.run(&mut cx).await.unwrap();
+
+    assert_eq!(1, cx.destinations.len());
+    assert!(cx.destinations[0]
+        .key
+        .starts_with("tickets/ticket/state/updated/494_"));
+}
@@ -0,0 +1,206 @@
+use aws_lambda_events::sqs::SqsMessage;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    config::PipelineConfig,
+    domain::{
+        admin::Availability,
+        company::Company,
+        conversation::{Conversation, Statistics, Tag},
+        notification::Notification,
+        ping::Heartbeat,
+        timestamp::TimestampFormat,
+    },
+    workflow::{Decode, Parse, Pipeline, RecordContext},
+};
+
+use super::{conversation_json, conversation_notification, dt, s};
+
+const NOTIFICATION_JSON: &str = include_str!("./data_files/notification.json");
+
+const FORMATS: [TimestampFormat; 3] = [
+    TimestampFormat::EpochSeconds,
+    TimestampFormat::EpochMillis,
+    TimestampFormat::Rfc3339,
+];
+
+/// What 2022-09-19T14:20:23Z is written as
+fn written(format: TimestampFormat) -> serde_json::Value {
+    match format {
+        TimestampFormat::EpochSeconds => serde_json::json!(1663597223),
+        TimestampFormat::EpochMillis => serde_json::json!(1663597223000_i64),
+        TimestampFormat::Rfc3339 => serde_json::json!("2022-09-19T14:20:23Z"),
+    }
+}
+
+fn round_trip<T>(format: TimestampFormat, value: &T) -> T
+where
+    T: Serialize + DeserializeOwned,
+{
+    format.scope(|| serde_json::from_value(serde_json::to_value(value).unwrap()).unwrap())
+}
+
+fn statistics() -> Statistics {
+    serde_json::from_value(conversation_json()["statistics"].clone()).unwrap()
+}
+
+#[test]
+fn timestamps_are_written_in_the_format() {
+    let conversation: Conversation = serde_json::from_value(conversation_json()).unwrap();
+
+    for format in FORMATS {
+        let document = format
+            .scope(|| serde_json::to_value(&conversation))
+            .unwrap();
+
+        assert_eq!(written(format), document["created_at"], "{format:?}");
+        assert_eq!(
+            written(format),
+            document["tags"][0]["applied_at"],
+            "{format:?}"
+        );
+        assert_eq!(
+            written(format),
+            document["first_contact_reply"]["created_at"],
+            "{format:?}"
+        );
+    }
+}
+
+#[test]
+fn timestamps_round_trip() {
+    let company: Company = serde_json::from_str::<Notification<Company>>(NOTIFICATION_JSON)
+        .unwrap()
+        .data;
+    let tag = Tag {
+        typ: s("tag"),
+        id: s("123456"),
+        name: s("Test tag"),
+        applied_at: dt("2022-09-19T14:20:23Z"),
+    };
+    let availability = Availability {
+        admin_id: s("991267"),
+        away: true,
+        reassign: false,
+        changed_at: dt("2014-02-18T13:48:51Z"),
+        notification_id: s("notif_1"),
+    };
+    let heartbeat = Heartbeat {
+        app_id: Some(s("a86dr8yl")),
+        message: None,
+        sent_at: dt("2024-06-27T12:54:40Z"),
+        received_at: dt("2024-06-27T12:57:45Z"),
+    };
+
+    for format in FORMATS {
+        assert_eq!(company, round_trip(format, &company), "{format:?}");
+        assert_eq!(
+            statistics(),
+            round_trip(format, &statistics()),
+            "{format:?}"
+        );
+        assert_eq!(tag, round_trip(format, &tag), "{format:?}");
+        assert_eq!(
+            availability,
+            round_trip(format, &availability),
+            "{format:?}"
+        );
+        assert_eq!(heartbeat, round_trip(format, &heartbeat), "{format:?}");
+    }
+}
+
+#[test]
+fn missing_timestamps_stay_null() {
+    let notification: Notification<Company> = serde_json::from_str(NOTIFICATION_JSON).unwrap();
+    assert_eq!(None, notification.data.last_request_at);
+
+    for format in FORMATS {
+        let document = format
+            .scope(|| serde_json::to_value(&notification))
+            .unwrap();
+        assert!(document["data"]["last_request_at"].is_null(), "{format:?}");
+    }
+}
+
+#[test]
+fn epoch_seconds_and_rfc_3339_are_read_outside_a_scope() {
+    let from_intercom: Tag = serde_json::from_value(serde_json::json!({
+        "type": "tag", "id": "1", "name": "a", "applied_at": 1663597223
+    }))
+    .unwrap();
+    let written: Tag = serde_json::from_value(serde_json::json!({
+        "type": "tag", "id": "1", "name": "a", "applied_at": "2022-09-19T14:20:23Z"
+    }))
+    .unwrap();
+
+    assert_eq!(dt("2022-09-19T14:20:23Z"), from_intercom.applied_at);
+    assert_eq!(from_intercom, written);
+}
+
+#[test]
+fn scopes_are_restored() {
+    let tag = Tag {
+        typ: s("tag"),
+        id: s("1"),
+        name: s("a"),
+        applied_at: dt("2022-09-19T14:20:23Z"),
+    };
+
+    let inner = TimestampFormat::EpochSeconds.scope(|| {
+        TimestampFormat::EpochMillis.scope(|| serde_json::to_value(&tag).unwrap());
+        serde_json::to_value(&tag).unwrap()
+    });
+    assert_eq!(serde_json::json!(1663597223), inner["applied_at"]);
+    assert_eq!(
+        serde_json::json!("2022-09-19T14:20:23Z"),
+        serde_json::to_value(&tag).unwrap()["applied_at"]
+    );
+}
+
+#[test]
+fn timestamps_config() {
+    let routes = "\n[[routes]]\ntopics = [\"*\"]\n";
+
+    assert_eq!(
+        TimestampFormat::Rfc3339,
+        PipelineConfig::parse(routes).unwrap().timestamps
+    );
+    assert_eq!(
+        TimestampFormat::EpochMillis,
+        PipelineConfig::parse(&format!("timestamps = \"epoch_millis\"{routes}"))
+            .unwrap()
+            .timestamps
+    );
+    assert!(PipelineConfig::parse(&format!("timestamps = \"iso8601\"{routes}")).is_err());
+}
+
+#[tokio::test]
+async fn envelopes_are_written_in_the_pipeline_format() {
+    let pipeline = Pipeline::new()
+        .with_timestamps(TimestampFormat::EpochSeconds)
+        .with_stage(Decode)
+        .with_stage(Parse);
+    let mut cx = RecordContext::new(&SqsMessage {
+        body: Some(conversation_notification(conversation_json())),
+        ..Default::default()
+    });
+    cx.received_at = dt("2024-06-27T12:57:45Z");
+    pipeline.run(&mut cx).await.unwrap();
+
+    let envelope = cx.envelope().unwrap();
+    let document = cx
+        .timestamps
+        .scope(|| serde_json::to_value(&envelope))
+        .unwrap();
+
+    assert_eq!("epoch_seconds", document["timestamp_format"]);
+    assert_eq!(serde_json::json!(1719493065), document["received_at"]);
+    assert_eq!(
+        serde_json::json!(1392731331),
+        document["notification"]["created_at"]
+    );
+    assert_eq!(
+        serde_json::json!(1663597223),
+        document["notification"]["data"]["created_at"]
+    );
+}
@@ -0,0 +1,193 @@
+use super::s;
+use chrono::TimeZone;
+
+use crate::{
+    config::{KeyFields, KeyTemplate},
+    domain::topic::*,
+};
+
+/// [Webhook topics](https://developers.intercom.com/docs/references/webhooks/webhook-models)
+/// documented by Intercom, each parses to a known topic
+const DOCUMENTED: &[&str] = &[
+    "admin.activity_log_event.created",
+    "admin.added_to_workspace",
+    "admin.away_mode_updated",
+    "admin.logged_in",
+    "admin.logged_out",
+    "admin.removed_from_workspace",
+    "company.contact.attached",
+    "company.contact.detached",
+    "company.created",
+    "company.deleted",
+    "company.updated",
+    "contact.archived",
+    "contact.attached_email",
+    "contact.deleted",
+    "contact.lead.added_email",
+    "contact.lead.created",
+    "contact.lead.signed_up",
+    "contact.lead.tag.created",
+    "contact.lead.tag.deleted",
+    "contact.lead.updated",
+    "contact.subscribed",
+    "contact.unarchived",
+    "contact.unsubscribed",
+    "contact.user.created",
+    "contact.user.tag.created",
+    "contact.user.tag.deleted",
+    "contact.user.updated",
+    "content_stat.article",
+    "content_stat.news_item",
+    "content_stat.series",
+    "conversation.admin.assigned",
+    "conversation.admin.closed",
+    "conversation.admin.noted",
+    "conversation.admin.open.assigned",
+    "conversation.admin.opened",
+    "conversation.admin.replied",
+    "conversation.admin.single.created",
+    "conversation.admin.snoozed",
+    "conversation.admin.unsnoozed",
+    "conversation.contact.attached",
+    "conversation.contact.detached",
+    "conversation.deleted",
+    "conversation.operator.replied",
+    "conversation.priority.updated",
+    "conversation.rating.added",
+    "conversation.read",
+    "conversation.user.created",
+    "conversation.user.replied",
+    "conversation_part.redacted",
+    "conversation_part.tag.created",
+    "event.created",
+    "job.completed",
+    "ping",
+    "ticket.admin.assigned",
+    "ticket.attribute.updated",
+    "ticket.contact.attached",
+    "ticket.contact.detached",
+    "ticket.contact.replied",
+    "ticket.created",
+    "ticket.note.created",
+    "ticket.rating.provided",
+    "ticket.state.updated",
+    "ticket.team.assigned",
+    "visitor.signed_up",
+];
+
+#[test]
+fn documented_topics_are_known() {
+    for documented in DOCUMENTED {
+        let topic = Topic::from(*documented);
+
+        assert!(topic.object().is_some(), "{documented}");
+        assert_eq!(*documented, topic.to_string());
+    }
+}
+
+#[test]
+fn topics_are_parsed_into_parts() {
+    assert_eq!(
+        Topic::Known {
+            object: Object::Conversation,
+            actor: Some(Actor::Admin),
+            action: s("closed"),
+        },
+        Topic::from("conversation.admin.closed")
+    );
+    assert_eq!(
+        Topic::Known {
+            object: Object::Contact,
+            actor: Some(Actor::User),
+            action: s("tag.created"),
+        },
+        Topic::from("contact.user.tag.created")
+    );
+    assert_eq!(
+        Topic::Known {
+            object: Object::ConversationPart,
+            actor: None,
+            action: s("redacted"),
+        },
+        Topic::from("conversation_part.redacted")
+    );
+    assert_eq!(
+        Topic::Known {
+            object: Object::Ping,
+            actor: None,
+            action: s(""),
+        },
+        Topic::from("ping")
+    );
+}
+
+#[test]
+fn unknown_topics_are_kept() {
+    for unknown in [
+        "",
+        "subscription.created",
+        "conversation",
+        "conversation.",
+        "conversation..closed",
+        "ping.pong",
+        "Conversation.admin.closed",
+        "conversation.admin/../closed",
+    ] {
+        let topic = Topic::from(unknown);
+
+        assert_eq!(Topic::Unknown(s(unknown)), topic);
+        assert_eq!("unknown", topic.label());
+        assert_eq!(unknown, topic.to_string());
+    }
+}
+
+#[test]
+fn topics_serialize_as_strings() {
+    let topic: Topic = serde_json::from_str(r#""ticket.state.updated""#).unwrap();
+
+    assert_eq!(Some(Object::Ticket), topic.object());
+    assert_eq!(Some("state.updated"), topic.action());
+    assert_eq!(
+        r#""ticket.state.updated""#,
+        serde_json::to_string(&topic).unwrap()
+    );
+    assert_eq!(
+        r#""subscription.created""#,
+        serde_json::to_string(&Topic::from("subscription.created")).unwrap()
+    );
+}
+
+#[test]
+fn topics_match_globs() {
+    let topic = Topic::from("conversation.admin.closed");
+
+    assert!(topic.matches("conversation.*"));
+    assert!(topic.matches("*.closed"));
+    assert!(!topic.matches("contact.*"));
+    assert!(topic == "conversation.admin.closed");
+}
+
+#[test]
+fn key_templates_use_the_topic() {
+    let template: KeyTemplate = "{object}/{topic_path}/{topic}_{uuid}.json".parse().unwrap();
+    let render = |topic: &str| {
+        template.render(&KeyFields {
+            now: &chrono::Utc.with_ymd_and_hms(2024, 1, 2, 10, 30, 0).unwrap(),
+            topic: &topic.into(),
+            notification_id: "notif_1",
+            item_id: "1295",
+            app_id: "a86dr8yl",
+            uuid: &uuid::Uuid::nil(),
+        })
+    };
+
+    assert_eq!(
+        "conversation/conversation/admin/closed/conversation_admin_closed_00000000-0000-0000-0000-000000000000.json",
+        render("conversation.admin.closed")
+    );
+    // Unknown topics cannot add path segments to the key
+    assert_eq!(
+        "unknown/a_//_b/a____b_00000000-0000-0000-0000-000000000000.json",
+        render("a/../b")
+    );
+}
@@ -0,0 +1,216 @@
+use std::sync::{Arc, Mutex};
+
+use aws_lambda_events::sqs::{SqsMessage, SqsMessageAttribute};
+use futures::future::BoxFuture;
+
+use crate::{
+    error::{Error, Result},
+    workflow::{
+        Decode, ErrorPolicy, Flow, Parse, Pipeline, RecordContext, Stage, StageKind, Validate,
+        Verify,
+    },
+};
+
+use super::{conversation_json, conversation_notification, s};
+
+type Log = Arc<Mutex<Vec<&'static str>>>;
+
+/// Logs its name when run and returns a fixed outcome
+struct Fake {
+    name: &'static str,
+    kind: StageKind,
+    on_error: ErrorPolicy,
+    fail: bool,
+    flow: Flow,
+    log: Log,
+}
+impl Fake {
+    fn new(name: &'static str, kind: StageKind, log: &Log) -> Self {
+        Self {
+            name,
+            kind,
+            on_error: ErrorPolicy::Fail,
+            fail: false,
+            flow: Flow::Continue,
+            log: log.clone(),
+        }
+    }
+
+    fn failing(self, on_error: ErrorPolicy) -> Self {
+        Self {
+            on_error,
+            fail: true,
+            ..self
+        }
+    }
+
+    fn stopping(self) -> Self {
+        Self {
+            flow: Flow::Stop,
+            ..self
+        }
+    }
+}
+impl Stage for Fake {
+    fn kind(&self) -> StageKind {
+        self.kind
+    }
+
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn on_error(&self) -> ErrorPolicy {
+        self.on_error
+    }
+
+    fn run<'a>(&'a self, _cx: &'a mut RecordContext) -> BoxFuture<'a, Result<Flow>> {
+        Box::pin(async move {
+            self.log.lock().unwrap().push(self.name);
+            match self.fail {
+                true => Err(Error::Validation(s(self.name))),
+                false => Ok(self.flow),
+            }
+        })
+    }
+}
+
+fn context(body: Option<String>) -> RecordContext {
+    RecordContext::new(&SqsMessage {
+        body,
+        ..Default::default()
+    })
+}
+
+#[tokio::test]
+async fn stages_run_in_kind_order() {
+    let log = Log::default();
+    let pipeline = Pipeline::new()
+        .with_stage(Fake::new("sink", StageKind::Sink, &log))
+        .with_stage(Fake::new("parse", StageKind::Parse, &log))
+        .with_stage(Fake::new("enrich 1", StageKind::Enrich, &log))
+        .with_stage(Fake::new("decode", StageKind::Decode, &log))
+        .with_stage(Fake::new("enrich 2", StageKind::Enrich, &log));
+
+    pipeline.run(&mut context(None)).await.unwrap();
+
+    assert_eq!(
+        vec!["decode", "parse", "enrich 1", "enrich 2", "sink"],
+        *log.lock().unwrap()
+    );
+}
+
+#[tokio::test]
+async fn error_policies() {
+    let log = Log::default();
+    let pipeline = Pipeline::new()
+        .with_stage(Fake::new("enrich", StageKind::Enrich, &log).failing(ErrorPolicy::Skip))
+        .with_stage(Fake::new("sink", StageKind::Sink, &log));
+    assert!(pipeline.run(&mut context(None)).await.is_ok());
+    assert_eq!(vec!["enrich", "sink"], *log.lock().unwrap());
+
+    let log = Log::default();
+    let pipeline = Pipeline::new()
+        .with_stage(Fake::new("validate", StageKind::Validate, &log).failing(ErrorPolicy::Drop))
+        .with_stage(Fake::new("sink", StageKind::Sink, &log));
+    assert!(pipeline.run(&mut context(None)).await.is_ok());
+    assert_eq!(vec!["validate"], *log.lock().unwrap());
+
+    let log = Log::default();
+    let pipeline = Pipeline::new()
+        .with_stage(Fake::new("validate", StageKind::Validate, &log).failing(ErrorPolicy::Fail))
+        .with_stage(Fake::new("sink", StageKind::Sink, &log));
+    assert!(matches!(
+        pipeline.run(&mut context(None)).await,
+        Err(Error::Validation(_))
+    ));
+    assert_eq!(vec!["validate"], *log.lock().unwrap());
+}
+
+#[tokio::test]
+async fn stop_skips_later_stages() {
+    let log = Log::default();
+    let pipeline = Pipeline::new()
+        .with_stage(Fake::new("route", StageKind::Route, &log).stopping())
+        .with_stage(Fake::new("sink", StageKind::Sink, &log));
+
+    pipeline.run(&mut context(None)).await.unwrap();
+
+    assert_eq!(vec!["route"], *log.lock().unwrap());
+}
+
+#[tokio::test]
+async fn decode_parse_validate() {
+    let log = Log::default();
+    let pipeline = Pipeline::new()
+        .with_stage(Decode)
+        .with_stage(Parse)
+        .with_stage(Validate)
+        .with_stage(Fake::new("sink", StageKind::Sink, &log));
+
+    let mut cx = context(Some(conversation_notification(conversation_json())));
+    pipeline.run(&mut cx).await.unwrap();
+
+    let notification = cx.notification().unwrap();
+    assert_eq!("notif_1", notification.id);
+    assert_eq!("1295", notification.data.id());
+    assert_eq!(vec!["sink"], *log.lock().unwrap());
+
+    let log = Log::default();
+    let pipeline =
+        Pipeline::new()
+            .with_stage(Decode)
+            .with_stage(Fake::new("sink", StageKind::Sink, &log));
+    pipeline.run(&mut context(None)).await.unwrap();
+    assert!(log.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn verify_signature() {
+    let body = conversation_notification(conversation_json());
+    let verify = Verify::new("secret");
+
+    let signature = {
+        use hmac::{Hmac, Mac};
+        let mut mac = Hmac::<sha1::Sha1>::new_from_slice(b"secret").unwrap();
+        mac.update(body.as_bytes());
+        format!("sha1={}", hex::encode(mac.finalize().into_bytes()))
+    };
+
+    let signed = |signature: &str| SqsMessage {
+        body: Some(body.clone()),
+        message_attributes: [(
+            s(Verify::ATTRIBUTE),
+            SqsMessageAttribute {
+                string_value: Some(s(signature)),
+                data_type: Some(s("String")),
+                ..Default::default()
+            },
+        )]
+        .into_iter()
+        .collect(),
+        ..Default::default()
+    };
+
+    let pipeline = Pipeline::new().with_stage(Decode).with_stage(verify);
+
+    assert!(pipeline
+        .run(&mut RecordContext::new(&signed(&signature)))
+        .await
+        .is_ok());
+
+    let tampered = signature.replace("sha1=", "sha1=00");
+    for record in [
+        signed(&tampered),
+        signed("not a signature"),
+        SqsMessage {
+            body: Some(body.clone()),
+            ..Default::default()
+        },
+    ] {
+        assert!(matches!(
+            pipeline.run(&mut RecordContext::new(&record)).await,
+            Err(Error::SignatureVerification(_))
+        ));
+    }
+}
@@ -4,4 +4,19 @@ pub(crate) trait Pipe: Sized {
     }
 }
 
-impl<T> Pipe for T { }
+impl<T> Pipe for T {}
+
+/// Match `value` against a pattern where `*` matches any run of characters
+pub(crate) fn glob_match(pattern: &str, value: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == value,
+        Some((prefix, rest)) => {
+            let Some(value) = value.strip_prefix(prefix) else {
+                return false;
+            };
+            (0..=value.len())
+                .filter(|&i| value.is_char_boundary(i))
+                .any(|i| glob_match(rest, &value[i..]))
+        }
+    }
+}
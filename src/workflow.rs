@@ -1 +1,1379 @@
+use std::sync::{Arc, OnceLock};
 
+use aws_lambda_events::sqs::SqsMessage;
+use aws_sdk_s3::Client as S3Client;
+use futures::future::BoxFuture;
+use hmac::{Hmac, Mac};
+use lambda_runtime::tracing::{self, Instrument};
+use serde::Deserialize;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::{
+    alerts::{Alert, AlertDedup, AlertRule},
+    config::{Filter, FilterAction, KeyFields, KeyTemplate, OutputFormat, Route, SinkConfig},
+    deserialize, deserialize_notification,
+    directory::CachedDirectory,
+    domain::{
+        admin::Availability,
+        contact::Contact,
+        conversation::Conversation,
+        notification::Notification,
+        ping::{Heartbeat, PingNotification},
+        timestamp::TimestampFormat,
+        topic::{Object, Topic},
+        DateTime, Item,
+    },
+    envelope::Envelope,
+    error::{Error, Result},
+    intercom::IntercomClient,
+};
+
+/// The order stages run in, each kind may have any number of stages
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum StageKind {
+    Decode,
+    Verify,
+    /// Keeps the body as it was received, before anything can fail to parse it
+    Archive,
+    Parse,
+    Validate,
+    Enrich,
+    Transform,
+    Route,
+    Sink,
+}
+
+/// What happens to a record when a stage fails
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorPolicy {
+    /// Stop the pipeline and fail the record, so it is retried or quarantined
+    #[default]
+    Fail,
+    /// Log the error and carry on with the next stage
+    Skip,
+    /// Log the error and acknowledge the record without running later stages
+    Drop,
+}
+
+/// Whether the record continues to the next stage
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow {
+    Continue,
+    /// The record is finished and later stages are not run
+    Stop,
+}
+
+/// Where a sink should write a record
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Destination {
+    pub bucket: String,
+    pub key: String,
+    pub format: OutputFormat,
+    /// Written instead of the record document, for streams derived from the record
+    pub document: Option<serde_json::Value>,
+}
+
+/// State of a record as it moves through the pipeline, each stage fills in its part
+#[derive(Debug, Clone)]
+pub struct RecordContext {
+    pub record: SqsMessage,
+    /// Set by the decode stage
+    pub body: Option<String>,
+    /// Set by the parse stage, the notification with its item as it was received
+    pub raw: Option<Notification<serde_json::Value>>,
+    /// The typed view of the notification, parsed from the body the first time a stage
+    /// needs it, or why it could not be
+    typed: OnceLock<std::result::Result<Notification<Item>, String>>,
+    /// Set by transform stages, the document written by sinks
+    /// when it differs from the serialized notification
+    pub output: Option<serde_json::Value>,
+    /// Set by filters, the name of the route to use instead of the one matching the topic
+    pub route: Option<String>,
+    /// Set by the route stage
+    pub destinations: Vec<Destination>,
+    /// Keys written by the sink stage
+    pub written: Vec<String>,
+    /// When processing started, recorded in the output envelope
+    pub received_at: DateTime,
+    /// How timestamps are written, set by the pipeline
+    pub timestamps: TimestampFormat,
+}
+impl RecordContext {
+    pub fn new(record: &SqsMessage) -> Self {
+        Self {
+            record: record.clone(),
+            body: None,
+            raw: None,
+            typed: OnceLock::new(),
+            output: None,
+            route: None,
+            destinations: vec![],
+            written: vec![],
+            received_at: chrono::Utc::now(),
+            timestamps: TimestampFormat::default(),
+        }
+    }
+
+    fn body(&self) -> Result<&str> {
+        self.body
+            .as_deref()
+            .ok_or_else(|| Error::Validation("record has not been decoded".into()))
+    }
+
+    fn raw(&self) -> Result<&Notification<serde_json::Value>> {
+        self.raw
+            .as_ref()
+            .ok_or_else(|| Error::Validation("record has not been parsed".into()))
+    }
+
+    /// The typed view of the notification, an error for topics the model does not represent
+    ///
+    /// The first error is returned as it is and later calls return its message, so a failure
+    /// is only counted once however many stages ask
+    pub fn notification(&self) -> Result<&Notification<Item>> {
+        let body = self.body()?;
+        self.raw()?;
+
+        let mut error = None;
+        let typed = self.typed.get_or_init(|| {
+            deserialize_notification(body).map_err(|e| {
+                let message = e.to_string();
+                error = Some(e);
+                message
+            })
+        });
+        match (typed, error) {
+            (Ok(notification), _) => Ok(notification),
+            (Err(_), Some(e)) => Err(e),
+            (Err(message), None) => Err(Error::Validation(message.clone())),
+        }
+    }
+
+    /// The `{uuid}` of the keys `stream` writes for the record, derived from the SQS message id
+    /// so a record delivered again overwrites its objects rather than adding new ones
+    pub fn key_uuid(&self, stream: &str) -> Uuid {
+        match &self.record.message_id {
+            Some(message_id) => {
+                let digest = Sha256::digest(format!("{stream}/{message_id}").as_bytes());
+                let mut bytes = [0; 16];
+                bytes.copy_from_slice(&digest[..16]);
+                uuid::Builder::from_custom_bytes(bytes).into_uuid()
+            }
+            None => Uuid::new_v4(),
+        }
+    }
+
+    /// Whether the topic is about `object`, known without parsing the item
+    fn is_about(&self, object: Object) -> Result<bool> {
+        Ok(self.raw()?.topic.object() == Some(object))
+    }
+
+    /// The typed view if a stage has already parsed it
+    pub fn parsed(&self) -> Option<&Notification<Item>> {
+        self.typed.get().and_then(|x| x.as_ref().ok())
+    }
+
+    /// The document to write, the transformed output or else the notification
+    pub fn document(&self) -> Result<serde_json::Value> {
+        match &self.output {
+            Some(output) => Ok(output.clone()),
+            None => {
+                let notification = self.notification()?;
+                self.timestamps
+                    .scope(|| serde_json::to_value(notification))
+                    .map_err(|e| Error::Validation(format!("unserializable notification: {e}")))
+            }
+        }
+    }
+
+    /// The document wrapped with what the record was and who wrote it
+    pub fn envelope(&self) -> Result<Envelope<serde_json::Value>> {
+        Ok(Envelope::new(
+            self.received_at,
+            self.timestamps,
+            self.record.message_id.clone(),
+            self.raw()?.topic.clone(),
+            self.document()?,
+        ))
+    }
+}
+
+pub trait Stage: Send + Sync {
+    fn kind(&self) -> StageKind;
+
+    /// Used to label the stage span
+    fn name(&self) -> &'static str;
+
+    fn on_error(&self) -> ErrorPolicy {
+        ErrorPolicy::Fail
+    }
+
+    fn run<'a>(&'a self, cx: &'a mut RecordContext) -> BoxFuture<'a, Result<Flow>>;
+}
+
+#[derive(Default)]
+pub struct Pipeline {
+    stages: Vec<Box<dyn Stage>>,
+    timestamps: TimestampFormat,
+}
+impl Pipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How timestamps are written in the output of every record
+    pub fn with_timestamps(mut self, timestamps: TimestampFormat) -> Self {
+        self.timestamps = timestamps;
+        self
+    }
+
+    /// Stages are ordered by their kind, stages of the same kind run in the order they are added
+    pub fn with_stage(mut self, stage: impl Stage + 'static) -> Self {
+        let index = self
+            .stages
+            .partition_point(|existing| existing.kind() <= stage.kind());
+        self.stages.insert(index, Box::new(stage));
+        self
+    }
+
+    pub async fn run(&self, cx: &mut RecordContext) -> Result<()> {
+        cx.timestamps = self.timestamps;
+        for stage in &self.stages {
+            let span = tracing::info_span!("stage", stage.name = stage.name());
+            let result = stage.run(cx).instrument(span).await;
+
+            match (result, stage.on_error()) {
+                (Ok(Flow::Continue), _) => {}
+                (Ok(Flow::Stop), _) => return Ok(()),
+                (Err(e), ErrorPolicy::Fail) => return Err(e),
+                (Err(e), ErrorPolicy::Skip) => {
+                    tracing::warn!(error = e.to_string(), stage = stage.name(), "stage skipped");
+                }
+                (Err(e), ErrorPolicy::Drop) => {
+                    tracing::warn!(
+                        error = e.to_string(),
+                        stage = stage.name(),
+                        "record dropped"
+                    );
+                    return Ok(());
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Takes the message body, records without one have nothing to process
+pub struct Decode;
+impl Stage for Decode {
+    fn kind(&self) -> StageKind {
+        StageKind::Decode
+    }
+
+    fn name(&self) -> &'static str {
+        "decode"
+    }
+
+    fn run<'a>(&'a self, cx: &'a mut RecordContext) -> BoxFuture<'a, Result<Flow>> {
+        Box::pin(async move {
+            cx.body = cx.record.body.clone();
+
+            match cx.body {
+                Some(_) => Ok(Flow::Continue),
+                None => Ok(Flow::Stop),
+            }
+        })
+    }
+}
+
+/// Checks the `X-Hub-Signature` Intercom sends with every notification,
+/// an HMAC-SHA1 of the body keyed with the app client secret
+///
+/// The header is expected to be forwarded as a message attribute
+pub struct Verify {
+    client_secret: String,
+}
+impl Verify {
+    pub const ATTRIBUTE: &'static str = "X-Hub-Signature";
+
+    pub fn new(client_secret: impl Into<String>) -> Self {
+        Self {
+            client_secret: client_secret.into(),
+        }
+    }
+
+    pub fn verify(&self, body: &str, signature: &str) -> Result<()> {
+        let signature = signature
+            .strip_prefix("sha1=")
+            .and_then(|x| hex::decode(x).ok())
+            .ok_or_else(|| Error::SignatureVerification("malformed signature".into()))?;
+
+        let mut mac = Hmac::<Sha1>::new_from_slice(self.client_secret.as_bytes())
+            .map_err(|e| Error::configuration("INTERCOM_CLIENT_SECRET", e))?;
+        mac.update(body.as_bytes());
+
+        mac.verify_slice(&signature)
+            .map_err(|_| Error::SignatureVerification("signature does not match body".into()))
+    }
+}
+impl Stage for Verify {
+    fn kind(&self) -> StageKind {
+        StageKind::Verify
+    }
+
+    fn name(&self) -> &'static str {
+        "verify"
+    }
+
+    fn run<'a>(&'a self, cx: &'a mut RecordContext) -> BoxFuture<'a, Result<Flow>> {
+        Box::pin(async move {
+            let signature = cx
+                .record
+                .message_attributes
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(Self::ATTRIBUTE))
+                .and_then(|(_, attribute)| attribute.string_value.as_deref())
+                .ok_or_else(|| Error::SignatureVerification("missing signature".into()))?;
+
+            self.verify(cx.body()?, signature)?;
+
+            Ok(Flow::Continue)
+        })
+    }
+}
+
+/// Writes the body exactly as it was received under its own key template, so a record
+/// the typed model cannot represent is never lost
+///
+/// Keys are filled in from whatever the body has of a notification, with `unknown` for the
+/// rest, and the uuid is [`RecordContext::key_uuid`] so a retried record overwrites its copy
+pub struct ArchiveRaw {
+    client: S3Client,
+    bucket: String,
+    key_template: KeyTemplate,
+}
+impl ArchiveRaw {
+    pub fn new(client: S3Client, bucket: String, key_template: KeyTemplate) -> Self {
+        Self {
+            client,
+            bucket,
+            key_template,
+        }
+    }
+
+    pub fn key(&self, cx: &RecordContext, now: &DateTime) -> Result<String> {
+        let body: serde_json::Value = serde_json::from_str(cx.body()?).unwrap_or_default();
+        let field = |value: &serde_json::Value| match value {
+            serde_json::Value::String(x) => x.clone(),
+            serde_json::Value::Number(x) => x.to_string(),
+            _ => "unknown".into(),
+        };
+
+        let uuid = cx.key_uuid("raw");
+
+        Ok(self.key_template.render(&KeyFields {
+            now,
+            topic: &field(&body["topic"]).as_str().into(),
+            notification_id: &field(&body["id"]),
+            item_id: &field(&body["data"]["item"]["id"]),
+            app_id: &field(&body["app_id"]),
+            uuid: &uuid,
+        }))
+    }
+}
+impl Stage for ArchiveRaw {
+    fn kind(&self) -> StageKind {
+        StageKind::Archive
+    }
+
+    fn name(&self) -> &'static str {
+        "archive_raw"
+    }
+
+    fn run<'a>(&'a self, cx: &'a mut RecordContext) -> BoxFuture<'a, Result<Flow>> {
+        Box::pin(async move {
+            let key = self.key(cx, &chrono::Utc::now())?;
+
+            self.client
+                .put_object()
+                .bucket(&self.bucket)
+                .key(&key)
+                .body(cx.body()?.as_bytes().to_vec().into())
+                .send()
+                .await
+                .map_err(|e| Error::sink_write(&self.bucket, e))?;
+
+            cx.written.push(key);
+            Ok(Flow::Continue)
+        })
+    }
+}
+
+/// Where the heartbeat of `ping` notifications is written, one object per workspace
+pub struct HeartbeatObject {
+    pub client: S3Client,
+    pub bucket: String,
+    pub prefix: String,
+}
+impl HeartbeatObject {
+    async fn write(&self, heartbeat: &Heartbeat, timestamps: TimestampFormat) -> Result<String> {
+        let app_id = heartbeat.app_id.as_deref().unwrap_or("unknown");
+        let key = format!("{}{app_id}.json", self.prefix);
+        let content = timestamps
+            .scope(|| serde_json::to_vec(heartbeat))
+            .map_err(|e| Error::Validation(format!("unserializable heartbeat: {e}")))?;
+
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(&key)
+            .body(content.into())
+            .send()
+            .await
+            .map_err(|e| Error::sink_write(&self.bucket, e))?;
+
+        Ok(key)
+    }
+}
+
+/// Acknowledges `ping` notifications, which Intercom sends when a webhook subscription
+/// is created or tested, as a heartbeat rather than parsing them as a notification
+pub struct Heartbeats {
+    object: Option<HeartbeatObject>,
+}
+impl Heartbeats {
+    pub fn new(object: Option<HeartbeatObject>) -> Self {
+        Self { object }
+    }
+}
+impl Stage for Heartbeats {
+    fn kind(&self) -> StageKind {
+        StageKind::Parse
+    }
+
+    fn name(&self) -> &'static str {
+        "heartbeats"
+    }
+
+    fn run<'a>(&'a self, cx: &'a mut RecordContext) -> BoxFuture<'a, Result<Flow>> {
+        Box::pin(async move {
+            #[derive(Deserialize)]
+            struct Header {
+                topic: Topic,
+            }
+
+            let body = cx.body()?;
+            let Header { topic } = deserialize(body)?;
+            if topic.object() != Some(Object::Ping) {
+                return Ok(Flow::Continue);
+            }
+
+            let ping: PingNotification = deserialize(body)?;
+            let heartbeat = Heartbeat::new(&ping, chrono::Utc::now());
+            tracing::info!(
+                monotonic_counter.heartbeats = 1_u64,
+                app_id = heartbeat.app_id.as_deref().unwrap_or("unknown"),
+            );
+
+            if let Some(object) = &self.object {
+                let key = object.write(&heartbeat, cx.timestamps).await?;
+                cx.written.push(key);
+            }
+
+            Ok(Flow::Stop)
+        })
+    }
+}
+
+/// Reads the notification with its item as it was received, the typed view of the item
+/// is only parsed when a later stage needs it
+pub struct Parse;
+impl Stage for Parse {
+    fn kind(&self) -> StageKind {
+        StageKind::Parse
+    }
+
+    fn name(&self) -> &'static str {
+        "parse"
+    }
+
+    fn run<'a>(&'a self, cx: &'a mut RecordContext) -> BoxFuture<'a, Result<Flow>> {
+        Box::pin(async move {
+            cx.raw = Some(deserialize(cx.body()?)?);
+            Ok(Flow::Continue)
+        })
+    }
+}
+
+/// Acknowledges notifications whose topic the typed model does not represent, such as
+/// `visitor.*` or unknown topics, rather than failing them. Only used with [`ArchiveRaw`],
+/// which has already kept their body
+pub struct SkipUnmodelled;
+impl Stage for SkipUnmodelled {
+    fn kind(&self) -> StageKind {
+        StageKind::Parse
+    }
+
+    fn name(&self) -> &'static str {
+        "skip_unmodelled"
+    }
+
+    fn run<'a>(&'a self, cx: &'a mut RecordContext) -> BoxFuture<'a, Result<Flow>> {
+        Box::pin(async move {
+            let topic = &cx.raw()?.topic;
+            if topic.object().is_some_and(|x| x.is_modelled()) {
+                return Ok(Flow::Continue);
+            }
+
+            tracing::info!(
+                monotonic_counter.unmodelled_records = 1_u64,
+                notification.topic = topic.to_string(),
+                "topic without a model kept as raw only"
+            );
+            Ok(Flow::Stop)
+        })
+    }
+}
+
+/// Rejects payloads that parse but are not notifications
+pub struct Validate;
+impl Stage for Validate {
+    fn kind(&self) -> StageKind {
+        StageKind::Validate
+    }
+
+    fn name(&self) -> &'static str {
+        "validate"
+    }
+
+    fn run<'a>(&'a self, cx: &'a mut RecordContext) -> BoxFuture<'a, Result<Flow>> {
+        Box::pin(async move {
+            let notification = cx.raw()?;
+
+            if notification.typ != "notification_event" {
+                return Err(Error::Validation(format!(
+                    "unexpected notification type {:?}",
+                    notification.typ
+                )));
+            }
+
+            Ok(Flow::Continue)
+        })
+    }
+}
+
+fn find_route<'a>(routes: &'a [Route], cx: &RecordContext) -> Result<Option<&'a Route>> {
+    if let Some(name) = &cx.route {
+        return Ok(routes
+            .iter()
+            .find(|route| route.name.as_ref() == Some(name)));
+    }
+
+    let notification = cx.raw()?;
+    Ok(routes
+        .iter()
+        .find(|route| route.matches(&notification.topic, &notification.app_id)))
+}
+
+/// Rejects notifications from workspaces that are not in the allowlist, when there is one
+pub struct AcceptWorkspaces {
+    allow: Option<Vec<String>>,
+}
+impl AcceptWorkspaces {
+    pub fn new(allow: Option<Vec<String>>) -> Self {
+        Self { allow }
+    }
+}
+impl Stage for AcceptWorkspaces {
+    fn kind(&self) -> StageKind {
+        StageKind::Validate
+    }
+
+    fn name(&self) -> &'static str {
+        "accept_workspaces"
+    }
+
+    fn run<'a>(&'a self, cx: &'a mut RecordContext) -> BoxFuture<'a, Result<Flow>> {
+        Box::pin(async move {
+            let app_id = &cx.raw()?.app_id;
+
+            if self.allow.as_ref().is_some_and(|x| !x.contains(app_id)) {
+                return Err(Error::Validation(format!(
+                    "workspace {app_id:?} is not allowed"
+                )));
+            }
+
+            Ok(Flow::Continue)
+        })
+    }
+}
+
+/// Applies the configured filters in order, a drop ends the record
+/// while the first route and every tag of the matching filters are kept
+pub struct ApplyFilters {
+    filters: Vec<Filter>,
+}
+impl ApplyFilters {
+    pub fn new(filters: Vec<Filter>) -> Self {
+        Self { filters }
+    }
+}
+impl Stage for ApplyFilters {
+    fn kind(&self) -> StageKind {
+        StageKind::Validate
+    }
+
+    fn name(&self) -> &'static str {
+        "filter"
+    }
+
+    fn run<'a>(&'a self, cx: &'a mut RecordContext) -> BoxFuture<'a, Result<Flow>> {
+        Box::pin(async move {
+            if self.filters.is_empty() {
+                return Ok(Flow::Continue);
+            }
+
+            let notification = cx.notification()?;
+            let matching: Vec<_> = self
+                .filters
+                .iter()
+                .filter(|filter| filter.when.matches(notification))
+                .collect();
+            let object = notification.topic.label();
+            let mut labels = vec![];
+
+            for filter in matching {
+                match &filter.action {
+                    FilterAction::Drop => {
+                        tracing::info!(filter.name = filter.name, "record dropped by filter");
+                        tracing::info!(
+                            monotonic_counter.dropped_records = 1_u64,
+                            filter.name = filter.name,
+                            topic.object = object,
+                        );
+                        return Ok(Flow::Stop);
+                    }
+                    FilterAction::Route { route } => {
+                        if cx.route.is_none() {
+                            cx.route = Some(route.clone());
+                        }
+                    }
+                    FilterAction::Tag { label } => {
+                        if !labels.contains(label) {
+                            labels.push(label.clone());
+                        }
+                    }
+                }
+            }
+
+            if !labels.is_empty() {
+                let mut document = cx.document()?;
+                document["labels"] = serde_json::json!(labels);
+                cx.output = Some(document);
+            }
+
+            Ok(Flow::Continue)
+        })
+    }
+}
+
+/// Acknowledges records whose topic matches no route from the topic alone, before they are
+/// parsed, so topics the model does not represent are not failed for lack of a route
+///
+/// Topics that a routing filter may send elsewhere are kept, the filters decide once the
+/// notification is parsed.
+pub struct AcceptTopics {
+    routes: Arc<Vec<Route>>,
+    /// The topics of filters that route, `None` for filters on every topic
+    rerouted: Vec<Option<Vec<String>>>,
+}
+impl AcceptTopics {
+    pub fn new(routes: Arc<Vec<Route>>) -> Self {
+        Self {
+            routes,
+            rerouted: vec![],
+        }
+    }
+
+    pub fn with_filters(mut self, filters: &[Filter]) -> Self {
+        self.rerouted = filters
+            .iter()
+            .filter(|x| matches!(x.action, FilterAction::Route { .. }))
+            .map(|x| x.when.topics.clone())
+            .collect();
+        self
+    }
+}
+impl Stage for AcceptTopics {
+    fn kind(&self) -> StageKind {
+        StageKind::Parse
+    }
+
+    fn name(&self) -> &'static str {
+        "accept_topics"
+    }
+
+    fn run<'a>(&'a self, cx: &'a mut RecordContext) -> BoxFuture<'a, Result<Flow>> {
+        Box::pin(async move {
+            #[derive(Deserialize)]
+            struct Header {
+                topic: Topic,
+                app_id: String,
+            }
+
+            let Header { topic, app_id } = deserialize(cx.body()?)?;
+            let routed = self.routes.iter().any(|x| x.matches(&topic, &app_id));
+            let rerouted = self.rerouted.iter().any(|topics| {
+                topics
+                    .as_ref()
+                    .is_none_or(|topics| topics.iter().any(|x| topic.matches(x)))
+            });
+            if routed || rerouted {
+                return Ok(Flow::Continue);
+            }
+
+            tracing::info!(notification.topic = topic.to_string(), "topic not accepted");
+            Ok(Flow::Stop)
+        })
+    }
+}
+
+/// Copy the values of `source` that `target` is missing or has as null, recursing into objects
+pub fn merge_missing(target: &mut serde_json::Value, source: serde_json::Value) {
+    let (serde_json::Value::Object(target), serde_json::Value::Object(source)) = (target, source)
+    else {
+        return;
+    };
+
+    for (key, value) in source {
+        match target.get_mut(&key) {
+            None | Some(serde_json::Value::Null) => {
+                target.insert(key, value);
+            }
+            Some(existing) => merge_missing(existing, value),
+        }
+    }
+}
+
+/// Copy the values of `source` over those of `target`, recursing into objects, nulls in
+/// `source` leave the value of `target` as it is
+pub fn merge_over(target: &mut serde_json::Value, source: serde_json::Value) {
+    let serde_json::Value::Object(source) = source else {
+        if !source.is_null() {
+            *target = source;
+        }
+        return;
+    };
+    let Some(target) = target.as_object_mut() else {
+        *target = serde_json::Value::Object(source);
+        return;
+    };
+
+    for (key, value) in source {
+        match target.get_mut(&key) {
+            Some(existing) => merge_over(existing, value),
+            None if !value.is_null() => {
+                target.insert(key, value);
+            }
+            None => {}
+        }
+    }
+}
+
+/// Restores what the webhook payload omits or truncates from the Intercom REST API,
+/// the full conversation and optionally each of its contacts
+pub struct Enrich {
+    client: IntercomClient,
+    topics: Vec<String>,
+    contacts: bool,
+    on_error: ErrorPolicy,
+    /// Applied to each contact before it is merged, as [`ProtectPii`] does for contact topics
+    pii: PiiHandling,
+    pii_fields: Vec<String>,
+}
+impl Enrich {
+    pub fn new(
+        client: IntercomClient,
+        topics: Vec<String>,
+        contacts: bool,
+        on_error: ErrorPolicy,
+    ) -> Self {
+        Self {
+            client,
+            topics,
+            contacts,
+            on_error,
+            pii: PiiHandling::Keep,
+            pii_fields: vec![],
+        }
+    }
+
+    pub fn with_pii(mut self, handling: PiiHandling, fields: Vec<String>) -> Self {
+        self.pii = handling;
+        self.pii_fields = fields;
+        self
+    }
+}
+impl Stage for Enrich {
+    fn kind(&self) -> StageKind {
+        StageKind::Enrich
+    }
+
+    fn name(&self) -> &'static str {
+        "enrich"
+    }
+
+    fn on_error(&self) -> ErrorPolicy {
+        self.on_error
+    }
+
+    fn run<'a>(&'a self, cx: &'a mut RecordContext) -> BoxFuture<'a, Result<Flow>> {
+        Box::pin(async move {
+            let topic = &cx.raw()?.topic;
+            if !cx.is_about(Object::Conversation)?
+                || !self.topics.iter().any(|pattern| topic.matches(pattern))
+            {
+                return Ok(Flow::Continue);
+            }
+            let Some(conversation) = cx.notification()?.data.as_conversation() else {
+                return Ok(Flow::Continue);
+            };
+
+            // Responses are read into the model and written back out, so only the fields and
+            // timestamp format of the model reach the output, never message bodies. They are
+            // merged over the webhook payload, whose conversation parts may be cut short
+            let contacts = if self.contacts {
+                let requests = conversation.contacts.iter().map(|contact| async {
+                    let contact = self.client.contact(&contact.reference.id).await?;
+                    deserialize::<Contact>(&contact.to_string())
+                });
+                futures::future::try_join_all(requests).await?
+            } else {
+                vec![]
+            };
+            let conversation = self.client.conversation(&conversation.id).await?;
+            let conversation = deserialize::<Conversation>(&conversation.to_string())?;
+
+            let (conversation, contacts) = cx
+                .timestamps
+                .scope(|| {
+                    let contacts: serde_json::Result<Vec<_>> =
+                        contacts.iter().map(serde_json::to_value).collect();
+                    Ok::<_, serde_json::Error>((serde_json::to_value(&conversation)?, contacts?))
+                })
+                .map_err(|e| Error::Validation(format!("unserializable enrichment: {e}")))?;
+
+            let mut document = cx.document()?;
+            let data = &mut document["data"];
+            merge_over(data, conversation);
+            if let Some(references) = data["contacts"].as_array_mut() {
+                for (reference, mut contact) in references.iter_mut().zip(contacts) {
+                    self.pii.apply(&mut contact, &self.pii_fields);
+                    merge_over(reference, contact);
+                }
+            }
+            cx.output = Some(document);
+
+            Ok(Flow::Continue)
+        })
+    }
+}
+
+/// Adds the names and emails of the admins and teams a conversation refers to,
+/// as `admin_assignee`, `team_assignee`, `statistics.last_closed_by` and on each teammate
+pub struct ResolveDirectory {
+    directory: CachedDirectory,
+    on_error: ErrorPolicy,
+}
+impl ResolveDirectory {
+    pub fn new(directory: CachedDirectory, on_error: ErrorPolicy) -> Self {
+        Self {
+            directory,
+            on_error,
+        }
+    }
+}
+impl Stage for ResolveDirectory {
+    fn kind(&self) -> StageKind {
+        StageKind::Enrich
+    }
+
+    fn name(&self) -> &'static str {
+        "resolve_directory"
+    }
+
+    fn on_error(&self) -> ErrorPolicy {
+        self.on_error
+    }
+
+    fn run<'a>(&'a self, cx: &'a mut RecordContext) -> BoxFuture<'a, Result<Flow>> {
+        Box::pin(async move {
+            if !cx.is_about(Object::Conversation)? {
+                return Ok(Flow::Continue);
+            }
+            let Some(conversation) = cx.notification()?.data.as_conversation() else {
+                return Ok(Flow::Continue);
+            };
+            let directory = self.directory.get().await?;
+            let admin = |id: &str| directory.admin(id).map(serde_json::to_value);
+
+            let admin_assignee = conversation
+                .admin_assignee_id
+                .and_then(|id| admin(&id.to_string()));
+            let team_assignee = conversation
+                .team_assignee_id
+                .as_deref()
+                .and_then(|id| directory.team(id))
+                .map(serde_json::to_value);
+            let last_closed_by = conversation
+                .statistics
+                .as_ref()
+                .and_then(|x| admin(&x.last_closed_by_id));
+            let teammates: Vec<_> = conversation
+                .teammates
+                .iter()
+                .map(|x| admin(&x.id))
+                .collect();
+
+            let unserializable = |e| Error::Validation(format!("unserializable directory: {e}"));
+            let mut document = cx.document()?;
+            let data = &mut document["data"];
+            if let Some(admin) = admin_assignee {
+                data["admin_assignee"] = admin.map_err(unserializable)?;
+            }
+            if let Some(team) = team_assignee {
+                data["team_assignee"] = team.map_err(unserializable)?;
+            }
+            if let Some(admin) = last_closed_by {
+                if data["statistics"].is_object() {
+                    data["statistics"]["last_closed_by"] = admin.map_err(unserializable)?;
+                }
+            }
+            if let Some(references) = data["teammates"].as_array_mut() {
+                for (reference, admin) in references.iter_mut().zip(teammates) {
+                    if let Some(admin) = admin {
+                        merge_missing(reference, admin.map_err(unserializable)?);
+                    }
+                }
+            }
+            cx.output = Some(document);
+
+            Ok(Flow::Continue)
+        })
+    }
+}
+
+/// Remove the value at `path` from `value`, `*` matches every key or array element
+pub fn redact(value: &mut serde_json::Value, path: &[&str]) {
+    let Some((segment, rest)) = path.split_first() else {
+        return;
+    };
+
+    match (value, *segment) {
+        (serde_json::Value::Object(object), "*") if rest.is_empty() => object.clear(),
+        (serde_json::Value::Object(object), "*") => {
+            object.values_mut().for_each(|x| redact(x, rest))
+        }
+        (serde_json::Value::Object(object), key) if rest.is_empty() => {
+            object.remove(key);
+        }
+        (serde_json::Value::Object(object), key) => {
+            if let Some(x) = object.get_mut(key) {
+                redact(x, rest)
+            }
+        }
+        (serde_json::Value::Array(array), "*") if rest.is_empty() => array.clear(),
+        (serde_json::Value::Array(array), "*") => array.iter_mut().for_each(|x| redact(x, rest)),
+        _ => {}
+    }
+}
+
+/// What is stored in place of the personal details of a contact
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PiiHandling {
+    /// Store the values unchanged
+    #[default]
+    Keep,
+    /// Remove the fields
+    Redact,
+    /// Replace values with the hex SHA-256 digest of the string, or of the JSON for other values,
+    /// so records about the same person can still be joined
+    Hash,
+}
+impl PiiHandling {
+    /// Apply to the top-level `fields` of `contact`, null values are left as they are
+    pub fn apply(&self, contact: &mut serde_json::Value, fields: &[String]) {
+        let Some(contact) = contact.as_object_mut() else {
+            return;
+        };
+
+        for field in fields {
+            match self {
+                Self::Keep => {}
+                Self::Redact => {
+                    contact.remove(field);
+                }
+                Self::Hash => {
+                    let Some(value) = contact.get_mut(field).filter(|x| !x.is_null()) else {
+                        continue;
+                    };
+                    let digest = match &*value {
+                        serde_json::Value::String(x) => Sha256::digest(x.as_bytes()),
+                        other => Sha256::digest(other.to_string().as_bytes()),
+                    };
+                    *value = hex::encode(digest).into();
+                }
+            }
+        }
+    }
+}
+
+/// Applies the PII handling to the contact of contact notifications
+pub struct ProtectPii {
+    handling: PiiHandling,
+    fields: Vec<String>,
+}
+impl ProtectPii {
+    pub fn new(handling: PiiHandling, fields: Vec<String>) -> Self {
+        Self { handling, fields }
+    }
+}
+impl Stage for ProtectPii {
+    fn kind(&self) -> StageKind {
+        StageKind::Transform
+    }
+
+    fn name(&self) -> &'static str {
+        "protect_pii"
+    }
+
+    fn run<'a>(&'a self, cx: &'a mut RecordContext) -> BoxFuture<'a, Result<Flow>> {
+        Box::pin(async move {
+            if !cx.is_about(Object::Contact)? {
+                return Ok(Flow::Continue);
+            }
+            let is_tag = match &cx.notification()?.data {
+                Item::Contact(_) => false,
+                Item::ContactTag(_) => true,
+                _ => return Ok(Flow::Continue),
+            };
+            if self.handling == PiiHandling::Keep {
+                return Ok(Flow::Continue);
+            }
+
+            let mut document = cx.document()?;
+            let contact = match is_tag {
+                true => &mut document["data"]["contact"],
+                false => &mut document["data"],
+            };
+            self.handling.apply(contact, &self.fields);
+            cx.output = Some(document);
+
+            Ok(Flow::Continue)
+        })
+    }
+}
+
+/// Applies the redaction policy of the matching route to the output document
+pub struct Redact {
+    routes: Arc<Vec<Route>>,
+}
+impl Redact {
+    pub fn new(routes: Arc<Vec<Route>>) -> Self {
+        Self { routes }
+    }
+}
+impl Stage for Redact {
+    fn kind(&self) -> StageKind {
+        StageKind::Transform
+    }
+
+    fn name(&self) -> &'static str {
+        "redact"
+    }
+
+    fn run<'a>(&'a self, cx: &'a mut RecordContext) -> BoxFuture<'a, Result<Flow>> {
+        Box::pin(async move {
+            let Some(route) = find_route(&self.routes, cx)? else {
+                return Ok(Flow::Continue);
+            };
+            if route.redact.is_empty() {
+                return Ok(Flow::Continue);
+            }
+
+            let mut document = cx.document()?;
+            for path in &route.redact {
+                redact(&mut document, &path.split('.').collect::<Vec<_>>());
+            }
+            cx.output = Some(document);
+
+            Ok(Flow::Continue)
+        })
+    }
+}
+
+/// Sends records to the sinks of the matching route, keyed by its template
+pub struct RouteByTopic {
+    routes: Arc<Vec<Route>>,
+}
+impl RouteByTopic {
+    pub fn new(routes: Arc<Vec<Route>>) -> Self {
+        Self { routes }
+    }
+}
+impl Stage for RouteByTopic {
+    fn kind(&self) -> StageKind {
+        StageKind::Route
+    }
+
+    fn name(&self) -> &'static str {
+        "route"
+    }
+
+    fn run<'a>(&'a self, cx: &'a mut RecordContext) -> BoxFuture<'a, Result<Flow>> {
+        Box::pin(async move {
+            let Some(route) = find_route(&self.routes, cx)? else {
+                return Ok(Flow::Continue);
+            };
+
+            let notification = cx.notification()?;
+            let key = route.key_template.render(&KeyFields {
+                now: &chrono::Utc::now(),
+                topic: &notification.topic,
+                notification_id: &notification.id,
+                item_id: notification.data.id(),
+                app_id: &notification.app_id,
+                uuid: &cx.key_uuid("route"),
+            });
+
+            for sink in &route.sinks {
+                let SinkConfig::S3 { bucket } = sink;
+                let bucket = bucket
+                    .clone()
+                    .ok_or_else(|| Error::configuration("sinks", "bucket not resolved"))?;
+
+                cx.destinations.push(Destination {
+                    bucket,
+                    key: key.clone(),
+                    format: route.format,
+                    document: None,
+                });
+            }
+
+            Ok(Flow::Continue)
+        })
+    }
+}
+
+/// Adds the teammate availability fact of `admin.away_mode_updated` notifications
+/// as a destination of its own
+pub struct RouteAvailability {
+    bucket: String,
+    key_template: KeyTemplate,
+    format: OutputFormat,
+}
+impl RouteAvailability {
+    pub fn new(bucket: String, key_template: KeyTemplate, format: OutputFormat) -> Self {
+        Self {
+            bucket,
+            key_template,
+            format,
+        }
+    }
+}
+impl Stage for RouteAvailability {
+    fn kind(&self) -> StageKind {
+        StageKind::Route
+    }
+
+    fn name(&self) -> &'static str {
+        "route_availability"
+    }
+
+    fn run<'a>(&'a self, cx: &'a mut RecordContext) -> BoxFuture<'a, Result<Flow>> {
+        Box::pin(async move {
+            if cx.raw()?.topic != Availability::TOPIC {
+                return Ok(Flow::Continue);
+            }
+            let notification = cx.notification()?;
+            let Item::Admin(admin) = &notification.data else {
+                return Ok(Flow::Continue);
+            };
+
+            let availability = Availability::new(notification, admin);
+            let document = cx
+                .timestamps
+                .scope(|| serde_json::to_value(&availability))
+                .map_err(|e| Error::Validation(format!("unserializable availability: {e}")))?;
+            let key = self.key_template.render(&KeyFields {
+                now: &chrono::Utc::now(),
+                topic: &notification.topic,
+                notification_id: &notification.id,
+                item_id: &admin.id,
+                app_id: &notification.app_id,
+                uuid: &cx.key_uuid("availability"),
+            });
+
+            cx.destinations.push(Destination {
+                bucket: self.bucket.clone(),
+                key,
+                format: self.format,
+                document: Some(document),
+            });
+            tracing::info!(
+                monotonic_counter.availability_changes = 1_u64,
+                admin.away = availability.away
+            );
+
+            Ok(Flow::Continue)
+        })
+    }
+}
+
+/// Writes the output document, in its envelope, to every destination
+pub struct S3Sink {
+    client: S3Client,
+}
+impl S3Sink {
+    pub fn new(client: S3Client) -> Self {
+        Self { client }
+    }
+}
+impl Stage for S3Sink {
+    fn kind(&self) -> StageKind {
+        StageKind::Sink
+    }
+
+    fn name(&self) -> &'static str {
+        "s3_sink"
+    }
+
+    fn run<'a>(&'a self, cx: &'a mut RecordContext) -> BoxFuture<'a, Result<Flow>> {
+        Box::pin(async move {
+            if cx.destinations.is_empty() {
+                return Ok(Flow::Continue);
+            }
+            let envelope = cx.envelope()?;
+            let envelope = cx
+                .timestamps
+                .scope(|| serde_json::to_value(envelope))
+                .map_err(|e| Error::Validation(format!("unserializable envelope: {e}")))?;
+
+            for destination in &cx.destinations {
+                let content = destination
+                    .format
+                    .encode(destination.document.as_ref().unwrap_or(&envelope))
+                    .map_err(|e| Error::Validation(format!("unencodable document: {e}")))?;
+
+                self.client
+                    .put_object()
+                    .bucket(&destination.bucket)
+                    .key(&destination.key)
+                    .body(content.into())
+                    .send()
+                    .await
+                    .map_err(|e| Error::sink_write(&destination.bucket, e))?;
+
+                cx.written.push(destination.key.clone());
+            }
+
+            Ok(Flow::Continue)
+        })
+    }
+}
+
+/// Sends an alert for each rule the conversation matches, unless it already alerted
+/// for that rule within the dedup window
+///
+/// Runs once the record is stored. A failed alert fails the record so it is delivered again,
+/// rewriting the same objects, while the rules that did alert are skipped as duplicates
+pub struct Alerts {
+    rules: Vec<AlertRule>,
+    dedup: AlertDedup,
+}
+impl Alerts {
+    pub fn new(rules: Vec<AlertRule>, dedup: AlertDedup) -> Self {
+        Self { rules, dedup }
+    }
+
+    async fn alert(
+        &self,
+        rule: &AlertRule,
+        notification: &Notification<Item>,
+        conversation: &Conversation,
+        timestamps: TimestampFormat,
+    ) -> Result<()> {
+        let now = chrono::Utc::now();
+        let conversation_id = &conversation.id;
+
+        if self
+            .dedup
+            .is_duplicate(&rule.name, conversation_id, &now, rule.dedup_window)
+            .await?
+        {
+            tracing::info!(
+                monotonic_counter.alerts_suppressed = 1_u64,
+                alert.rule = rule.name
+            );
+            return Ok(());
+        }
+
+        let alert = Alert::new(&rule.name, notification, conversation, now);
+        for sink in &rule.sinks {
+            if let Err(e) = sink.send(&alert, timestamps).await {
+                self.dedup.release(&rule.name, conversation_id, &now).await;
+                return Err(e);
+            }
+        }
+        self.dedup.record(&rule.name, conversation_id, now).await?;
+
+        tracing::info!(
+            monotonic_counter.alerts_sent = 1_u64,
+            alert.rule = rule.name
+        );
+        Ok(())
+    }
+}
+impl Stage for Alerts {
+    fn kind(&self) -> StageKind {
+        StageKind::Sink
+    }
+
+    fn name(&self) -> &'static str {
+        "alerts"
+    }
+
+    fn run<'a>(&'a self, cx: &'a mut RecordContext) -> BoxFuture<'a, Result<Flow>> {
+        Box::pin(async move {
+            if !cx.is_about(Object::Conversation)? {
+                return Ok(Flow::Continue);
+            }
+            let notification = cx.notification()?;
+            let Some(conversation) = notification.data.as_conversation() else {
+                return Ok(Flow::Continue);
+            };
+            let mut result = Ok(Flow::Continue);
+
+            for rule in self.rules.iter().filter(|x| x.when.matches(notification)) {
+                if let Err(e) = self
+                    .alert(rule, notification, conversation, cx.timestamps)
+                    .await
+                {
+                    tracing::warn!(
+                        error = e.to_string(),
+                        alert.rule = rule.name,
+                        "alert failed"
+                    );
+                    result = Err(e);
+                }
+            }
+
+            result
+        })
+    }
+}